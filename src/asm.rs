@@ -0,0 +1,288 @@
+//! Assembler for the syntax the disassembler prints, so a dump can be
+//! edited and assembled back into a ROM.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! start:
+//!     IRMov v0,0a
+//!     Index@sprite
+//!     Draw v0,v1,5
+//! loop:
+//!     Jmp@loop
+//! sprite:
+//!     db f0 90 f0 90 f0
+//! ```
+//!
+//! Addresses are `@x` followed by hex or `@label`, immediates are hex and
+//! the `Draw` height is decimal. Disassembly lines (`000200:\t6a02\t|\tIRMov
+//! vA,02`) are accepted too; if the mnemonic still matches the raw word the
+//! word is kept as is, so a dump assembles back to the same bytes.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Chip8, OpCode, PROG_OFFSET};
+
+pub struct Program {
+    pub bytes: Vec<u8>,
+    /// Source line, counting from 1, of each instruction and data directive
+    /// by address.
+    pub lines: BTreeMap<usize, usize>,
+    /// Addresses of the data directives among them.
+    pub data: BTreeSet<usize>,
+}
+
+/// Which source line each address came from, as JSON for debuggers:
+/// `{"source": "pong.s", "lines": {"512": 3, "514": 4}}`, addresses in
+/// decimal. Any compiler can write one for its own syntax.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceMap {
+    /// Relative to the map's own directory.
+    pub source: PathBuf,
+    pub lines: BTreeMap<usize, usize>,
+}
+
+impl SourceMap {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let mut map: SourceMap =
+            serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            map.source = dir.join(&map.source);
+        }
+        Ok(map)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// The line `addr` is on, or the closest one before it.
+    pub fn line(&self, addr: usize) -> Option<usize> {
+        self.lines.range(..=addr).next_back().map(|(_, line)| *line)
+    }
+
+    /// The first address on `line` or the next line with any, and that
+    /// line.
+    pub fn addr(&self, line: usize) -> Option<(usize, usize)> {
+        self.lines
+            .iter()
+            .filter(|(_, l)| **l >= line)
+            .min_by_key(|(addr, l)| (**l, **addr))
+            .map(|(addr, l)| (*addr, *l))
+    }
+}
+
+enum Stmt<'a> {
+    Op { text: &'a str, raw: Option<u16> },
+    Bytes(Vec<u8>),
+}
+
+impl Stmt<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Stmt::Op { .. } => 2,
+            Stmt::Bytes(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// Prints `prog` one instruction per line, loaded at `PROG_OFFSET`.
+pub fn disassemble(prog: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, word) in prog.chunks(2).enumerate() {
+        let addr = PROG_OFFSET + i * 2;
+        match *word {
+            [hi, lo] => {
+                let raw = ((hi as u16) << 8) | lo as u16;
+                out += &format!("{:06x}:\t{:04x}\t|\t{}\n", addr, raw, Chip8::decode(raw));
+            }
+            // odd length, the last byte can't be an instruction
+            [byte] => out += &format!("{:06x}:\t{:02x}\t|\tdb {:02x}\n", addr, byte, byte),
+            _ => unreachable!(),
+        }
+    }
+    out
+}
+
+pub fn assemble(src: &str) -> Result<Program, String> {
+    let mut labels = HashMap::new();
+    let mut stmts = vec![];
+    let mut addr = PROG_OFFSET;
+
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let err = |e: String| format!("line {line_no}: {e}");
+
+        let mut text = line.split(';').next().unwrap_or("").trim();
+        let mut raw = None;
+
+        if let Some((dump, op)) = text.rsplit_once('|') {
+            let word = dump.split_whitespace().nth(1).unwrap_or("");
+            raw = Some(u16::from_str_radix(word, 16).map_err(|_| err(format!("bad word {word}")))?);
+            text = op.trim();
+        } else if let Some((label, rest)) = text.split_once(':') {
+            if !is_label(label.trim()) {
+                return Err(err(format!("bad label {label}")));
+            }
+            if labels.insert(label.trim().to_owned(), addr).is_some() {
+                return Err(err(format!("duplicate label {label}")));
+            }
+            text = rest.trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let stmt = match text.strip_prefix("db ") {
+            Some(data) => Stmt::Bytes(
+                data.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|b| !b.is_empty())
+                    .map(|b| parse_hex(b).and_then(|b| u8::try_from(b).map_err(|e| e.to_string())))
+                    .collect::<Result<_, _>>()
+                    .map_err(err)?,
+            ),
+            None => Stmt::Op { text, raw },
+        };
+
+        let len = stmt.len();
+        stmts.push((line_no, addr, stmt));
+        addr += len;
+    }
+
+    let mut bytes = vec![];
+    let mut lines = BTreeMap::new();
+    let mut data = BTreeSet::new();
+    for (line_no, addr, stmt) in stmts {
+        lines.insert(addr, line_no);
+        match stmt {
+            Stmt::Bytes(db) => {
+                data.insert(addr);
+                bytes.extend(db);
+            }
+            Stmt::Op { text, raw } => {
+                let word = match raw {
+                    Some(raw) if Chip8::decode(raw).to_string() == text => raw,
+                    _ => parse_op(text, &labels)
+                        .and_then(|op| op.encode().ok_or_else(|| format!("can't assemble {text}")))
+                        .map_err(|e| format!("line {line_no}: {e}"))?,
+                };
+                bytes.extend(word.to_be_bytes());
+            }
+        }
+    }
+
+    Ok(Program { bytes, lines, data })
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("bad hex number {s}"))
+}
+
+fn parse_op(text: &str, labels: &HashMap<String, usize>) -> Result<OpCode, String> {
+    use OpCode::*;
+
+    let (name, args) = match text.find(|c: char| c == '@' || c.is_whitespace()) {
+        Some(idx) => (&text[..idx], text[idx..].trim()),
+        None => (text, ""),
+    };
+    let args: Vec<&str> = if args.is_empty() {
+        vec![]
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+
+    let reg = |s: &str| match s
+        .strip_prefix(['v', 'V'])
+        .map(|n| usize::from_str_radix(n, 16))
+    {
+        Some(Ok(n)) if n < 16 => Ok(n),
+        _ => Err(format!("bad register {s}")),
+    };
+    let imm = |s: &str| {
+        parse_hex(s).and_then(|n| u8::try_from(n).map_err(|_| format!("{s} is more than a byte")))
+    };
+    let addr = |s: &str| {
+        let s = s
+            .strip_prefix('@')
+            .ok_or_else(|| format!("expected @address, got {s}"))?;
+        let hex = s
+            .strip_prefix('x')
+            .filter(|h| !h.is_empty() && h.chars().all(|c| c.is_ascii_hexdigit()));
+        let addr = match hex {
+            Some(hex) => parse_hex(hex)? as usize,
+            None => *labels.get(s).ok_or_else(|| format!("unknown label {s}"))?,
+        };
+        if addr > 0xfff {
+            return Err(format!("address {addr:x} out of range"));
+        }
+        Ok(addr)
+    };
+
+    let op = match (name.to_lowercase().as_str(), args.as_slice()) {
+        ("dispclear", []) => DispClear,
+        ("ret", []) => Ret,
+        ("lores", []) => LoRes,
+        ("hires", []) => HiRes,
+        ("halt", []) => Halt,
+        ("nativecall", [a]) => {
+            let addr = addr(a)?;
+            if NativeCall(addr).encode().is_none() {
+                return Err(format!(
+                    "nativecall {a} would run as {}, use db",
+                    Chip8::decode(addr as u16)
+                ));
+            }
+            NativeCall(addr)
+        }
+        ("jmp", [a]) => Jmp(addr(a)?),
+        ("call", [a]) => Call(addr(a)?),
+        ("index", [a]) => Index(addr(a)?),
+        ("jmpadd", [a]) => JmpAdd(addr(a)?),
+        ("imeq", [x, i]) => ImEq(reg(x)?, imm(i)?),
+        ("imneq", [x, i]) => ImNeq(reg(x)?, imm(i)?),
+        ("irmov", [x, i]) => IRMov(reg(x)?, imm(i)?),
+        ("iradd", [x, i]) => IRAdd(reg(x)?, imm(i)?),
+        ("rand", [x, i]) => Rand(reg(x)?, imm(i)?),
+        ("rreq", [x, y]) => RREq(reg(x)?, reg(y)?),
+        ("rrmov", [x, y]) => RRMov(reg(x)?, reg(y)?),
+        ("rror", [x, y]) => RROr(reg(x)?, reg(y)?),
+        ("rrand", [x, y]) => RRAnd(reg(x)?, reg(y)?),
+        ("rrxor", [x, y]) => RRXor(reg(x)?, reg(y)?),
+        ("rradd", [x, y]) => RRAdd(reg(x)?, reg(y)?),
+        ("rrsub", [x, y]) => RRSub(reg(x)?, reg(y)?),
+        ("rrsub2", [x, y]) => RRSub2(reg(x)?, reg(y)?),
+        ("rrneq", [x, y]) => RRNeq(reg(x)?, reg(y)?),
+        ("rrshr", [x]) => RRShr(reg(x)?),
+        ("rrshl", [x]) => RRShl(reg(x)?),
+        ("keyeq", [x]) => KeyEq(reg(x)?),
+        ("keyneq", [x]) => KeyNeq(reg(x)?),
+        ("delayget", [x]) => DelayGet(reg(x)?),
+        ("keywait", [x]) => KeyWait(reg(x)?),
+        ("delayset", [x]) => DelaySet(reg(x)?),
+        ("soundset", [x]) => SoundSet(reg(x)?),
+        ("incindex", [x]) => IncIndex(reg(x)?),
+        ("spriteaddr", [x]) => SpriteAddr(reg(x)?),
+        ("bcd", [x]) => BCD(reg(x)?),
+        ("regdump", [x]) => RegDump(reg(x)?),
+        ("regload", [x]) => RegLoad(reg(x)?),
+        ("draw", [x, y, n]) => {
+            let n = n.parse::<u8>().ok().filter(|n| *n < 16);
+            Draw(reg(x)?, reg(y)?, n.ok_or("sprite height must be 0-15")?)
+        }
+        _ => return Err(format!("can't parse {text}")),
+    };
+
+    Ok(op)
+}
@@ -0,0 +1,153 @@
+//! User config, read from a toml file.
+//!
+//! ```toml
+//! palette = "amber"
+//! layout = "azerty"
+//!
+//! [palettes]
+//! gameboy = ["#0f380f", "#9bbc0f"]
+//!
+//! [keys]
+//! 5 = ["z", "up"]
+//!
+//! [roms.PONG.keys]
+//! 1 = ["up"]
+//! 4 = ["down"]
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::keymap::KeyMap;
+use crate::palette::Palette;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Name of the palette to start with, a preset or one of `palettes`.
+    pub palette: Option<String>,
+    /// Extra palettes of 2 to 4 colors, added after the presets.
+    pub palettes: BTreeMap<String, Vec<String>>,
+    /// Keyboard layout the keypad bindings start from, see [`KeyMap::layout`].
+    pub layout: Option<String>,
+    /// Host keys for keypad keys, by hex digit, replacing the layout's.
+    pub keys: BTreeMap<String, Vec<String>>,
+    /// Overrides for single ROMs, by file name without the extension.
+    pub roms: BTreeMap<String, RomConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomConfig {
+    pub layout: Option<String>,
+    pub keys: BTreeMap<String, Vec<String>>,
+}
+
+impl Config {
+    /// Loads `path`, or the default config file if there is one.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config {}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid config {}: {e}", path.display()))
+    }
+
+    /// The presets followed by the palettes defined in the config.
+    pub fn palettes(&self) -> Result<Vec<Palette>, String> {
+        let mut palettes = Palette::presets();
+        for (name, colors) in &self.palettes {
+            let palette = Palette::parse(name, colors)?;
+            match palettes.iter_mut().find(|p| p.name == *name) {
+                Some(existing) => *existing = palette,
+                None => palettes.push(palette),
+            }
+        }
+
+        Ok(palettes)
+    }
+
+    /// Key bindings for `rom`, the global ones with the ROM's overrides on top.
+    pub fn keymap(&self, rom: Option<&str>) -> Result<KeyMap, String> {
+        let rom = rom.and_then(|name| self.roms.get(name));
+        let layout = rom
+            .and_then(|r| r.layout.as_deref())
+            .or(self.layout.as_deref())
+            .unwrap_or("qwerty");
+
+        let mut keymap = KeyMap::layout(layout).ok_or_else(|| {
+            let names: Vec<&str> = KeyMap::layouts().collect();
+            format!(
+                "Unknown layout {layout}, expected one of {}",
+                names.join(", ")
+            )
+        })?;
+        keymap.apply(&self.keys)?;
+        if let Some(rom) = rom {
+            keymap.apply(&rom.keys)?;
+        }
+
+        Ok(keymap)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/chip8/config.toml`, falling back to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(dir.join("chip8").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        layout = "azerty"
+
+        [keys]
+        5 = ["up"]
+
+        [roms.PONG]
+        layout = "qwerty"
+
+        [roms.PONG.keys]
+        5 = ["down"]
+    "#;
+
+    #[test]
+    fn keymap_starts_from_the_layout() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let keymap = config.keymap(None).unwrap();
+        assert_eq!(keymap.get("a"), Some(0x4));
+        assert_eq!(keymap.get("up"), Some(0x5));
+        assert_eq!(keymap.get("z"), None);
+    }
+
+    #[test]
+    fn rom_overrides_come_last() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let keymap = config.keymap(Some("PONG")).unwrap();
+        assert_eq!(keymap.get("q"), Some(0x4));
+        assert_eq!(keymap.names(0x5), ["down"]);
+
+        // other ROMs only get the global bindings
+        assert_eq!(config.keymap(Some("TETRIS")), config.keymap(None));
+    }
+
+    #[test]
+    fn unknown_layouts_fail() {
+        let config: Config = toml::from_str(r#"layout = "colemak""#).unwrap();
+        assert!(config.keymap(None).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Which parts of a ROM a run touched: the instructions it ran, which way
+//! each skip went, and the bytes `Draw` and `RegLoad` read through `I`.
+//! Bytes only ever read are data, which the disassembler can't tell apart
+//! from code on its own.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::asm::Program;
+use crate::sprites::SpriteSize;
+use crate::{Chip8, OpCode, PROG_OFFSET};
+
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    /// Times each address was run.
+    pub executed: BTreeMap<usize, u64>,
+    /// Times each address was read as data.
+    pub read: BTreeMap<usize, u64>,
+    /// For each skip instruction, how many times it skipped and how many
+    /// it didn't.
+    pub branches: BTreeMap<usize, (u64, u64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    /// Counts `op` at `pc` once it ran with `I` at `i`, `skip` being
+    /// whether it skipped the next instruction and `hires` whether it ran in
+    /// high resolution.
+    pub fn record(&mut self, pc: usize, op: &OpCode, i: usize, skip: bool, hires: bool) {
+        *self.executed.entry(pc).or_default() += 1;
+
+        let read = match *op {
+            OpCode::Draw(_, _, n) => SpriteSize::drawn(n, hires).bytes(),
+            OpCode::RegLoad(vx) => vx + 1,
+            _ => 0,
+        };
+        for addr in i..i + read {
+            *self.read.entry(addr).or_default() += 1;
+        }
+
+        if is_skip(op) {
+            let (taken, not_taken) = self.branches.entry(pc).or_default();
+            match skip {
+                true => *taken += 1,
+                false => *not_taken += 1,
+            }
+        }
+    }
+
+    /// The disassembly of `prog` with how often each instruction ran and
+    /// bytes only read as data as `db`, in the syntax `asm` reads back.
+    pub fn annotate(&self, prog: &[u8]) -> String {
+        let end = PROG_OFFSET + prog.len();
+        let ran = |addr: usize| self.executed.contains_key(&addr);
+        let was_read = |addr: usize| self.read.contains_key(&addr);
+
+        let code = (PROG_OFFSET..end).filter(|a| ran(*a)).count() * 2;
+        let data = (PROG_OFFSET..end)
+            .filter(|a| was_read(*a) && !ran(*a))
+            .count();
+        let skips = self.branches.iter().filter(|(a, _)| **a < end).count();
+        let both = self
+            .branches
+            .iter()
+            .filter(|(a, (taken, not_taken))| **a < end && *taken > 0 && *not_taken > 0)
+            .count();
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "; {} of {} bytes run, {data} only read as data",
+            code.min(prog.len()),
+            prog.len()
+        );
+        let _ = writeln!(out, "; {both} of {skips} skips went both ways");
+
+        let mut addr = PROG_OFFSET;
+        while addr < end {
+            let byte = |a: usize| prog[a - PROG_OFFSET];
+            // code can start at an odd address after an odd amount of data
+            let len = match ran(addr) || !ran(addr + 1) {
+                true => 2.min(end - addr),
+                false => 1,
+            };
+            let bytes: Vec<u8> = (addr..addr + len).map(byte).collect();
+
+            let line = match bytes.as_slice() {
+                [hi, lo] if ran(addr) || !(was_read(addr) || was_read(addr + 1)) => {
+                    let raw = (*hi as u16) << 8 | *lo as u16;
+                    let op = Chip8::decode(raw);
+                    let note = match (self.executed.get(&addr), self.branches.get(&addr)) {
+                        (Some(count), Some((taken, not_taken))) => {
+                            format!("\t; {count}, skipped {taken}, not {not_taken}")
+                        }
+                        (Some(count), None) => format!("\t; {count}"),
+                        (None, _) => String::new(),
+                    };
+                    format!("{addr:06x}:\t{raw:04x}\t|\t{op}{note}")
+                }
+                _ => {
+                    let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+                    let note = match was_read(addr) || was_read(addr + len - 1) {
+                        true => "\t; data",
+                        false => "",
+                    };
+                    format!(
+                        "{addr:06x}:\t{}\t|\tdb {}{note}",
+                        hex.concat(),
+                        hex.join(" ")
+                    )
+                }
+            };
+            out += &line;
+            out.push('\n');
+            addr += len;
+        }
+
+        out
+    }
+
+    /// An LCOV tracefile for `source`, assembled into `prog`, with a
+    /// line for each instruction and a pair of branches for each skip.
+    pub fn lcov(&self, source: &str, prog: &Program) -> String {
+        let mut out = format!("TN:\nSF:{source}\n");
+        let (mut found, mut hit, mut branches, mut branches_hit) = (0, 0, 0, 0);
+
+        for (&addr, &line) in &prog.lines {
+            if prog.data.contains(&addr) {
+                continue;
+            }
+            let count = self.executed.get(&addr).copied().unwrap_or(0);
+            let offset = addr - PROG_OFFSET;
+            if let Some(word) = prog.bytes.get(offset..offset + 2) {
+                let op = Chip8::decode((word[0] as u16) << 8 | word[1] as u16);
+                if is_skip(&op) {
+                    // skipped is branch 0, carried on branch 1, and - for
+                    // both when it never ran
+                    let (skipped, not_skipped) = match self.branches.get(&addr) {
+                        Some((taken, not_taken)) => (taken.to_string(), not_taken.to_string()),
+                        None => ("-".to_owned(), "-".to_owned()),
+                    };
+                    for (n, times) in [skipped, not_skipped].iter().enumerate() {
+                        let _ = writeln!(out, "BRDA:{line},0,{n},{times}");
+                        branches += 1;
+                        branches_hit += (times != "-" && times != "0") as u32;
+                    }
+                }
+            }
+            let _ = writeln!(out, "DA:{line},{count}");
+            found += 1;
+            hit += (count > 0) as u32;
+        }
+
+        let _ = writeln!(out, "BRF:{branches}\nBRH:{branches_hit}");
+        let _ = writeln!(out, "LF:{found}\nLH:{hit}");
+        out += "end_of_record\n";
+        out
+    }
+}
+
+/// Whether `op` conditionally skips the next instruction.
+fn is_skip(op: &OpCode) -> bool {
+    use OpCode::*;
+    matches!(
+        op,
+        ImEq(..) | ImNeq(..) | RREq(..) | RRNeq(..) | KeyEq(..) | KeyNeq(..)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    /// Skips the jump back, draws the two bytes at `sprite` and spins.
+    const SOURCE: &str = "start:
+    ImEq v0,00
+    Jmp@start
+    Index@sprite
+    Draw v0,v0,2
+loop:
+    Jmp@loop
+sprite:
+    db f0 90
+";
+
+    fn run() -> (Coverage, asm::Program) {
+        let prog = asm::assemble(SOURCE).unwrap();
+        let mut comp = Chip8::new().with_coverage();
+        comp.load(&prog.bytes).unwrap();
+        for _ in 0..6 {
+            comp.step().unwrap();
+        }
+        (comp.coverage.unwrap(), prog)
+    }
+
+    #[test]
+    fn records_runs_reads_and_skips() {
+        let (coverage, _) = run();
+        let executed: Vec<(usize, u64)> = coverage.executed.into_iter().collect();
+        assert_eq!(executed, [(0x200, 1), (0x204, 1), (0x206, 1), (0x208, 3)]);
+        let read: Vec<usize> = coverage.read.into_keys().collect();
+        assert_eq!(read, [0x20a, 0x20b]);
+        assert_eq!(coverage.branches[&0x200], (1, 0));
+    }
+
+    #[test]
+    fn annotate_splits_code_from_data() {
+        let (coverage, prog) = run();
+        let listing = coverage.annotate(&prog.bytes);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "; 8 of 12 bytes run, 2 only read as data");
+        assert_eq!(lines[1], "; 0 of 1 skips went both ways");
+        assert!(lines[2].ends_with("\t; 1, skipped 1, not 0"));
+        // the jump never ran but is still code
+        assert!(lines[3].starts_with("000202:\t1200\t|\t"));
+        assert!(!lines[3].contains(';'));
+        assert!(lines[6].ends_with("\t; 3"));
+        assert_eq!(lines[7], "00020a:\tf090\t|\tdb f0 90\t; data");
+        assert_eq!(lines.len(), 8);
+    }
+
+    #[test]
+    fn lcov_has_a_line_per_instruction() {
+        let (coverage, prog) = run();
+        assert_eq!(
+            coverage.lcov("game.asm", &prog),
+            "TN:\nSF:game.asm\n\
+             BRDA:2,0,0,1\nBRDA:2,0,1,0\nDA:2,1\n\
+             DA:3,0\nDA:4,1\nDA:5,1\nDA:7,3\n\
+             BRF:2\nBRH:1\nLF:5\nLH:4\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn large_sprites_only_in_high_resolution() {
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, &OpCode::Draw(0, 0, 0), 0x300, false, false);
+        assert!(coverage.read.is_empty());
+        coverage.record(0x200, &OpCode::Draw(0, 0, 0), 0x300, false, true);
+        assert_eq!(coverage.read.len(), 32);
+    }
+}
@@ -0,0 +1,257 @@
+//! Software CRT look, applied on the CPU to a frame scaled up by a whole
+//! factor so it works without GPU shaders.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CrtEffects {
+    /// Darkens every other line.
+    pub scanlines: bool,
+    /// Darkens the edges of each chip8 pixel.
+    pub grid: bool,
+    /// Bleeds light from bright pixels into their neighbours.
+    pub bloom: bool,
+    /// Bends the picture like the glass of a tube.
+    pub curvature: bool,
+}
+
+impl CrtEffects {
+    pub fn all() -> Self {
+        CrtEffects {
+            scanlines: true,
+            grid: true,
+            bloom: true,
+            curvature: true,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.scanlines || self.grid || self.bloom || self.curvature
+    }
+}
+
+/// Parses a comma separated list like `scanlines,bloom`, or `all`.
+impl FromStr for CrtEffects {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut effects = CrtEffects::default();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "all" => effects = CrtEffects::all(),
+                "scanlines" => effects.scanlines = true,
+                "grid" => effects.grid = true,
+                "bloom" => effects.bloom = true,
+                "curvature" | "curve" => effects.curvature = true,
+                _ => return Err(format!("Unknown crt effect {name}")),
+            }
+        }
+
+        Ok(effects)
+    }
+}
+
+pub struct Crt {
+    pub effects: CrtEffects,
+    scaled: Vec<u8>,
+    scratch: Vec<u8>,
+}
+
+impl Crt {
+    pub fn new(effects: CrtEffects) -> Self {
+        Crt {
+            effects,
+            scaled: vec![],
+            scratch: vec![],
+        }
+    }
+
+    /// Scales the RGB24 `frame` up by `scale` and applies the enabled effects.
+    /// Returns the processed frame, which is `width * scale` by `height * scale`.
+    pub fn process(&mut self, frame: &[u8], width: usize, height: usize, scale: usize) -> &[u8] {
+        let scale = scale.max(1);
+        let (w, h) = (width * scale, height * scale);
+        self.scaled.resize(w * h * 3, 0);
+
+        for y in 0..h {
+            for x in 0..w {
+                let src = ((x / scale) + (y / scale) * width) * 3;
+                let dst = (x + y * w) * 3;
+                self.scaled[dst..dst + 3].copy_from_slice(&frame[src..src + 3]);
+            }
+        }
+
+        if self.effects.grid && scale >= 3 {
+            for y in 0..h {
+                for x in 0..w {
+                    if x % scale == scale - 1 || y % scale == scale - 1 {
+                        shade(&mut self.scaled, x + y * w, 0.55);
+                    }
+                }
+            }
+        }
+
+        if self.effects.scanlines && scale >= 2 {
+            for y in (1..h).step_by(2) {
+                for x in 0..w {
+                    shade(&mut self.scaled, x + y * w, 0.7);
+                }
+            }
+        }
+
+        if self.effects.bloom {
+            self.bloom(w, h, (scale / 2).max(1));
+        }
+
+        if self.effects.curvature {
+            self.curve(w, h);
+        }
+
+        &self.scaled
+    }
+
+    /// Adds a box blurred copy of the frame on top of itself.
+    fn bloom(&mut self, w: usize, h: usize, radius: usize) {
+        let mut blurred = std::mem::take(&mut self.scratch);
+        // rows then columns
+        blur(&self.scaled, &mut blurred, w, h, radius, w, 1);
+        let mut tmp = vec![];
+        blur(&blurred, &mut tmp, h, w, radius, 1, w);
+
+        for (px, glow) in self.scaled.iter_mut().zip(&tmp) {
+            *px = px.saturating_add(glow / 2);
+        }
+        self.scratch = blurred;
+    }
+
+    /// Barrel distortion, sampling each output pixel from further out the
+    /// closer it is to the corners. Anything outside the frame goes black.
+    fn curve(&mut self, w: usize, h: usize) {
+        const AMOUNT: f32 = 0.04;
+
+        self.scratch.clear();
+        self.scratch.resize(w * h * 3, 0);
+        for y in 0..h {
+            let v = y as f32 / h as f32 * 2.0 - 1.0;
+            for x in 0..w {
+                let u = x as f32 / w as f32 * 2.0 - 1.0;
+                let su = u * (1.0 + AMOUNT * v * v);
+                let sv = v * (1.0 + AMOUNT * u * u);
+                if su.abs() >= 1.0 || sv.abs() >= 1.0 {
+                    continue;
+                }
+
+                let sx = ((su + 1.0) / 2.0 * w as f32) as usize;
+                let sy = ((sv + 1.0) / 2.0 * h as f32) as usize;
+                let src = (sx + sy * w) * 3;
+                let dst = (x + y * w) * 3;
+                self.scratch[dst..dst + 3].copy_from_slice(&self.scaled[src..src + 3]);
+            }
+        }
+        std::mem::swap(&mut self.scaled, &mut self.scratch);
+    }
+}
+
+fn shade(buf: &mut [u8], pixel: usize, amount: f32) {
+    for c in &mut buf[pixel * 3..pixel * 3 + 3] {
+        *c = (*c as f32 * amount) as u8;
+    }
+}
+
+/// One pass of a box blur along lines of `len` pixels. `step` is the
+/// distance between neighbouring pixels in a line, `stride` between lines.
+fn blur(
+    src: &[u8],
+    dst: &mut Vec<u8>,
+    len: usize,
+    lines: usize,
+    radius: usize,
+    stride: usize,
+    step: usize,
+) {
+    dst.resize(src.len(), 0);
+    let window = (radius * 2 + 1) as u32;
+    for line in 0..lines {
+        let base = line * stride;
+        for c in 0..3 {
+            let at = |i: usize| src[(base + i * step) * 3 + c] as u32;
+            let mut sum: u32 = (0..=radius.min(len - 1)).map(at).sum();
+            for i in 0..len {
+                dst[(base + i * step) * 3 + c] = (sum / window) as u8;
+                if i + radius + 1 < len {
+                    sum += at(i + radius + 1);
+                }
+                if i >= radius {
+                    sum -= at(i - radius);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 frame with only the top left pixel lit.
+    const FRAME: [u8; 12] = [255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    /// The red channel of each pixel of a processed frame.
+    fn red(frame: &[u8]) -> Vec<u8> {
+        frame.iter().step_by(3).copied().collect()
+    }
+
+    #[test]
+    fn process_scales_up_by_whole_pixels() {
+        let mut crt = Crt::new(CrtEffects::default());
+        let out = crt.process(&FRAME, 2, 2, 2);
+        #[rustfmt::skip]
+        assert_eq!(red(out), [
+            255, 255, 0, 0,
+            255, 255, 0, 0,
+            0, 0, 0, 0,
+            0, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn scanlines_darken_every_other_line() {
+        let effects = CrtEffects {
+            scanlines: true,
+            ..Default::default()
+        };
+        let mut crt = Crt::new(effects);
+        let out = crt.process(&FRAME, 2, 2, 2);
+        assert_eq!(red(out)[..8], [255, 255, 0, 0, 178, 178, 0, 0]);
+    }
+
+    #[test]
+    fn grid_darkens_pixel_edges() {
+        let effects = CrtEffects {
+            grid: true,
+            ..Default::default()
+        };
+        let mut crt = Crt::new(effects);
+        let out = red(crt.process(&FRAME, 2, 2, 3));
+        assert_eq!(out[..3], [255, 255, 140]);
+        assert_eq!(out[12..15], [140, 140, 140]);
+    }
+
+    #[test]
+    fn blur_averages_over_the_radius() {
+        // one line of three gray pixels
+        let src = [90, 90, 90, 0, 0, 0, 0, 0, 0];
+        let mut dst = vec![];
+        blur(&src, &mut dst, 3, 1, 1, 3, 1);
+        assert_eq!(dst, [30, 30, 30, 30, 30, 30, 0, 0, 0]);
+    }
+
+    #[test]
+    fn parses_effect_lists() {
+        let effects: CrtEffects = "scanlines, bloom".parse().unwrap();
+        assert!(effects.scanlines && effects.bloom);
+        assert!(!effects.grid && !effects.curvature);
+        assert_eq!("all".parse(), Ok(CrtEffects::all()));
+        assert!("sparkles".parse::<CrtEffects>().is_err());
+    }
+}
@@ -0,0 +1,698 @@
+//! A Debug Adapter Protocol server on stdin and stdout, so editors can
+//! launch a ROM without a display, set breakpoints by source line, step
+//! and look at the registers, the stack and memory.
+//!
+//! `launch` takes the `program`, a ROM or a `.s` source to assemble, and
+//! for a ROM the `sourceMap` that `asm --map` writes, along with
+//! `stopOnEntry`, `tickrate` and `seed`. Call stack frames are named after
+//! the subroutine they're in, `main` or `sub_<addr>` like the profiler's.
+//! Running, the ROM keeps to the tickrate at 60 frames a second.
+
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::asm::{self, SourceMap};
+use crate::profile::Profile;
+use crate::{Chip8, OpCode, PROG_OFFSET};
+
+const REGISTERS: i64 = 1;
+const STACK: i64 = 2;
+
+/// What the machine does between requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Run {
+    Stopped,
+    Continue,
+    /// Until the stack pointer is back down to this, stepping over or out
+    /// of a call.
+    Until(usize),
+}
+
+/// Serves requests from `input` until the editor disconnects.
+pub fn serve(input: impl Read + Send + 'static, output: impl Write) -> Result<(), String> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(input);
+        while let Some(msg) = read_message(&mut reader) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut server = Server {
+        out: output,
+        seq: 0,
+        comp: None,
+        tickrate: 20,
+        stop_on_entry: false,
+        map: None,
+        breakpoints: BTreeMap::new(),
+        next_id: 1,
+        run: Run::Stopped,
+        resumed: false,
+    };
+    server.serve(rx).map_err(|e| e.to_string())
+}
+
+/// The next message, or `None` at the end of the input.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        match line.trim().split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("Content-Length") => {
+                len = value.trim().parse().ok();
+            }
+            None if line.trim().is_empty() => break,
+            _ => {}
+        }
+    }
+    let mut body = vec![0; len?];
+    reader.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+struct Server<W> {
+    out: W,
+    /// Sequence number of the last message sent.
+    seq: u64,
+    comp: Option<Chip8>,
+    tickrate: u32,
+    stop_on_entry: bool,
+    map: Option<SourceMap>,
+    /// Ids of the breakpoints by address.
+    breakpoints: BTreeMap<usize, u64>,
+    next_id: u64,
+    run: Run,
+    /// The next instruction is the one it stopped at, so a breakpoint
+    /// there doesn't stop it again.
+    resumed: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn serve(&mut self, rx: Receiver<Value>) -> io::Result<()> {
+        let frame = Duration::from_secs(1) / 60;
+        let mut next = Instant::now();
+        loop {
+            let msg = match self.run {
+                Run::Stopped => rx.recv().ok(),
+                _ => match rx.try_recv() {
+                    Ok(msg) => Some(msg),
+                    Err(TryRecvError::Empty) => {
+                        self.frame()?;
+                        next += frame;
+                        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => None,
+                },
+            };
+            let Some(msg) = msg else {
+                return Ok(());
+            };
+            if !self.request(&msg)? {
+                return Ok(());
+            }
+            next = Instant::now();
+        }
+    }
+
+    fn send(&mut self, mut msg: Value) -> io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.out.flush()
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        self.run = Run::Stopped;
+        let mut body = json!({"reason": reason, "threadId": 1, "allThreadsStopped": true});
+        if reason == "breakpoint" {
+            let pc = self.comp.as_ref().map(Chip8::pc);
+            let ids: Vec<u64> = pc
+                .and_then(|pc| self.breakpoints.get(&pc))
+                .into_iter()
+                .copied()
+                .collect();
+            body["hitBreakpointIds"] = json!(ids);
+        }
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    fn resume(&mut self, run: Run) {
+        self.run = run;
+        self.resumed = true;
+    }
+
+    fn machine(&self) -> Result<&Chip8, String> {
+        self.comp
+            .as_ref()
+            .ok_or_else(|| "No ROM was launched".to_owned())
+    }
+
+    /// Handles a request, false once the session is over.
+    fn request(&mut self, msg: &Value) -> io::Result<bool> {
+        let command = msg["command"].as_str().unwrap_or("");
+        let args = &msg["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsReadMemoryRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" | "pause" | "disconnect" | "terminate" => Ok(json!({})),
+            "threads" => Ok(json!({"threads": [{"id": 1, "name": "chip8"}]})),
+            "stackTrace" => self.machine().map(|comp| self.stack_trace(comp)),
+            "scopes" => Ok(json!({"scopes": [
+                {"name": "Registers", "variablesReference": REGISTERS, "expensive": false},
+                {"name": "Stack", "variablesReference": STACK, "expensive": false},
+            ]})),
+            "variables" => self
+                .machine()
+                .map(|comp| variables(comp, args["variablesReference"].as_i64())),
+            "readMemory" => self.machine().and_then(|comp| read_memory(comp, args)),
+            "continue" => self.machine().map(|_| json!({"allThreadsContinued": true})),
+            "next" | "stepIn" | "stepOut" => self.machine().map(|_| json!({})),
+            _ => Err(format!("{command} isn't supported")),
+        };
+
+        let ok = result.is_ok();
+        let response = match result {
+            Ok(body) => json!({
+                "type": "response", "request_seq": msg["seq"], "success": true,
+                "command": command, "body": body,
+            }),
+            Err(e) => json!({
+                "type": "response", "request_seq": msg["seq"], "success": false,
+                "command": command, "message": e,
+            }),
+        };
+        self.send(response)?;
+        if !ok {
+            return Ok(true);
+        }
+
+        match command {
+            "launch" => self.event("initialized", json!({}))?,
+            "configurationDone" => match self.stop_on_entry {
+                true => self.stopped("entry", None)?,
+                // a breakpoint on the first instruction still stops it
+                false => self.run = Run::Continue,
+            },
+            "continue" => self.resume(Run::Continue),
+            "next" | "stepIn" | "stepOut" => self.step_command(command)?,
+            "pause" => self.stopped("pause", None)?,
+            "disconnect" | "terminate" => {
+                self.event("terminated", json!({}))?;
+                return Ok(false);
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = PathBuf::from(
+            args["program"]
+                .as_str()
+                .ok_or("launch needs the program to run")?,
+        );
+        let (rom, map) = match program.extension().and_then(|e| e.to_str()) {
+            Some("s") => {
+                let src = std::fs::read_to_string(&program)
+                    .map_err(|e| format!("{}: {e}", program.display()))?;
+                let prog =
+                    asm::assemble(&src).map_err(|e| format!("{}: {e}", program.display()))?;
+                let map = SourceMap {
+                    source: program.clone(),
+                    lines: prog.lines,
+                };
+                (prog.bytes, Some(map))
+            }
+            _ => {
+                let rom =
+                    std::fs::read(&program).map_err(|e| format!("{}: {e}", program.display()))?;
+                let map = args["sourceMap"]
+                    .as_str()
+                    .map(|path| SourceMap::load(Path::new(path)))
+                    .transpose()?;
+                (rom, map)
+            }
+        };
+
+        let seed = args["seed"]
+            .as_u64()
+            .unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        let mut comp = Chip8::new().with_seed(seed);
+        comp.load(&rom)?;
+        self.comp = Some(comp);
+        self.map = map;
+        self.tickrate = args["tickrate"].as_u64().unwrap_or(20) as u32;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(json!({}))
+    }
+
+    /// Replaces the breakpoints, each on the first instruction at or after
+    /// its line.
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let source = &args["source"];
+        let path = source["path"].as_str().map(Path::new);
+        let map = self
+            .map
+            .as_ref()
+            .filter(|map| path.is_some_and(|path| same_file(path, &map.source)));
+
+        let mut breakpoints = BTreeMap::new();
+        let mut replies = vec![];
+        let lines = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        for bp in lines {
+            let line = bp["line"].as_u64().unwrap_or(0) as usize;
+            let found = map.and_then(|map| map.addr(line));
+            let reply = match found {
+                Some((addr, line)) => {
+                    let id = *breakpoints.entry(addr).or_insert_with(|| {
+                        match self.breakpoints.get(&addr) {
+                            Some(id) => *id,
+                            None => {
+                                self.next_id += 1;
+                                self.next_id - 1
+                            }
+                        }
+                    });
+                    json!({"id": id, "verified": true, "line": line, "source": source})
+                }
+                None => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No code at or after this line in the source map",
+                }),
+            };
+            replies.push(reply);
+        }
+
+        self.breakpoints = breakpoints;
+        Ok(json!({"breakpoints": replies}))
+    }
+
+    /// Frames from the innermost, at `pc`, out through the return
+    /// addresses on the stack to where each call was made.
+    fn stack_trace(&self, comp: &Chip8) -> Value {
+        let stack = comp.stack();
+        let mut pcs = vec![comp.pc()];
+        // a stack poked from elsewhere can hold anything
+        pcs.extend(stack.iter().rev().map(|ret| ret.saturating_sub(2)));
+
+        let frames: Vec<Value> = pcs
+            .iter()
+            .enumerate()
+            .map(|(k, &pc)| {
+                // the call that entered this frame's routine
+                let routine = match stack.len().checked_sub(k + 1) {
+                    Some(n) => {
+                        let call = stack[n].saturating_sub(2);
+                        match op_at(comp, call) {
+                            Some(OpCode::Call(addr)) => addr,
+                            _ => call,
+                        }
+                    }
+                    None => PROG_OFFSET,
+                };
+                let mut frame = json!({
+                    "id": k,
+                    "name": Profile::name(routine),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("0x{pc:03x}"),
+                });
+                if let Some(map) = &self.map {
+                    if let Some(line) = map.line(pc) {
+                        frame["line"] = json!(line);
+                        frame["column"] = json!(1);
+                        frame["source"] = json!({"path": map.source});
+                    }
+                }
+                frame
+            })
+            .collect();
+
+        json!({"stackFrames": frames, "totalFrames": pcs.len()})
+    }
+
+    fn step_command(&mut self, command: &str) -> io::Result<()> {
+        let Some(comp) = &self.comp else {
+            return Ok(());
+        };
+        let sp = comp.sp();
+        let at_call = matches!(op_at(comp, comp.pc()), Some(OpCode::Call(_)));
+        let returns = !comp.stack().is_empty();
+        match command {
+            "next" if at_call => self.resume(Run::Until(sp)),
+            "stepOut" if returns => self.resume(Run::Until(sp.saturating_sub(2))),
+            "stepOut" => self.resume(Run::Continue),
+            _ => {
+                if self.exec()? {
+                    self.stopped("step", None)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs one instruction, false if the program ended or failed.
+    fn exec(&mut self) -> io::Result<bool> {
+        let Some(comp) = &mut self.comp else {
+            return Ok(false);
+        };
+        let res = match comp.running {
+            true => comp.step(),
+            false => Ok(()),
+        };
+        match res {
+            Ok(()) if comp.running => Ok(true),
+            Ok(()) => {
+                self.run = Run::Stopped;
+                self.event("exited", json!({"exitCode": 0}))?;
+                self.event("terminated", json!({}))?;
+                Ok(false)
+            }
+            Err(e) => {
+                self.stopped("exception", Some(e))?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs a frame's worth of instructions while it isn't stopped.
+    fn frame(&mut self) -> io::Result<()> {
+        for _ in 0..self.tickrate {
+            let Some(comp) = &self.comp else {
+                return Ok(());
+            };
+            if !self.resumed && self.breakpoints.contains_key(&comp.pc()) {
+                return self.stopped("breakpoint", None);
+            }
+            self.resumed = false;
+
+            if !self.exec()? {
+                return Ok(());
+            }
+            let sp = self.comp.as_ref().map_or(0, Chip8::sp);
+            if matches!(self.run, Run::Until(until) if sp <= until) {
+                return self.stopped("step", None);
+            }
+        }
+        if let Some(comp) = &mut self.comp {
+            comp.dec_timers();
+        }
+        Ok(())
+    }
+}
+
+fn variables(comp: &Chip8, reference: Option<i64>) -> Value {
+    let var = |name: String, value: String| json!({"name": name, "value": value, "variablesReference": 0});
+    let vars: Vec<Value> = match reference {
+        Some(REGISTERS) => {
+            let mut vars: Vec<Value> = comp
+                .regs()
+                .iter()
+                .enumerate()
+                .map(|(n, v)| var(format!("V{n:X}"), format!("0x{v:02x}")))
+                .collect();
+            let i = comp.index();
+            let mut index = var("I".into(), format!("0x{i:03x}"));
+            index["memoryReference"] = json!(format!("0x{i:03x}"));
+            vars.push(index);
+            let at_i: Vec<String> = comp
+                .mem()
+                .iter()
+                .skip(i)
+                .take(16)
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            vars.push(var("[I]".into(), at_i.join(" ")));
+            vars.push(var("PC".into(), format!("0x{:03x}", comp.pc())));
+            vars.push(var("SP".into(), format!("0x{:03x}", comp.sp())));
+            vars.push(var("DT".into(), comp.delay_timer().to_string()));
+            vars.push(var("ST".into(), comp.sound_timer().to_string()));
+            vars
+        }
+        Some(STACK) => comp
+            .stack()
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(n, ret)| {
+                let mut v = var(n.to_string(), format!("0x{ret:03x}"));
+                v["memoryReference"] = json!(format!("0x{ret:03x}"));
+                v
+            })
+            .collect(),
+        _ => vec![],
+    };
+    json!({"variables": vars})
+}
+
+fn read_memory(comp: &Chip8, args: &Value) -> Result<Value, String> {
+    let reference = args["memoryReference"].as_str().unwrap_or("");
+    let base = match reference.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => reference.parse(),
+    }
+    .map_err(|_| format!("bad memory reference {reference}"))?;
+    let start = base
+        .checked_add(args["offset"].as_i64().unwrap_or(0))
+        .ok_or_else(|| format!("memory reference {reference} plus the offset overflows"))?;
+    let count = args["count"].as_u64().unwrap_or(0) as usize;
+
+    // the data has to start at the address asked for, so memory from there
+    // to the end is read and anything past it is unreadable
+    let mem = comp.mem();
+    let data = match usize::try_from(start) {
+        Ok(from) if from < mem.len() => &mem[from..from.saturating_add(count).min(mem.len())],
+        _ => &[],
+    };
+    let address = match start < 0 {
+        true => start.to_string(),
+        false => format!("0x{start:03x}"),
+    };
+    Ok(json!({
+        "address": address,
+        "data": base64(data),
+        "unreadableBytes": count - data.len(),
+    }))
+}
+
+fn op_at(comp: &Chip8, addr: usize) -> Option<OpCode> {
+    let word = comp.mem().get(addr..addr.checked_add(2)?)?;
+    Some(Chip8::decode((word[0] as u16) << 8 | word[1] as u16))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(CHARS[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{PipeReader, PipeWriter};
+
+    const PROG: &str = "\
+; counts v0 up in a subroutine
+start:
+    IRMov v0,00
+loop:
+    Call@bump
+    Index@sprite
+    Draw v0,v1,5
+    Jmp@loop
+
+bump:
+    IRAdd v0,01
+    Ret
+
+sprite:
+    db f0 90 f0 90 f0
+";
+
+    /// An editor's end of the session.
+    struct Client {
+        writer: PipeWriter,
+        reader: BufReader<PipeReader>,
+        seq: u64,
+        /// Events read while waiting for a response.
+        events: Vec<Value>,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, args: Value) -> Value {
+            self.seq += 1;
+            let body = json!({
+                "seq": self.seq, "type": "request", "command": command, "arguments": args,
+            })
+            .to_string();
+            write!(self.writer, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+
+            loop {
+                let msg = read_message(&mut self.reader).expect("a response");
+                match msg["type"].as_str() {
+                    Some("response") => {
+                        assert_eq!(msg["request_seq"], self.seq);
+                        assert_eq!(msg["success"], true, "{msg}");
+                        return msg["body"].clone();
+                    }
+                    _ => self.events.push(msg),
+                }
+            }
+        }
+
+        fn event(&mut self, event: &str) -> Value {
+            if let Some(n) = self.events.iter().position(|e| e["event"] == event) {
+                return self.events.remove(n)["body"].clone();
+            }
+            loop {
+                let msg = read_message(&mut self.reader).expect("an event");
+                if msg["event"] == event {
+                    return msg["body"].clone();
+                }
+            }
+        }
+
+        /// Line and name of each frame, innermost first.
+        fn frames(&mut self) -> Vec<(u64, String)> {
+            let trace = self.request("stackTrace", json!({"threadId": 1}));
+            trace["stackFrames"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|f| {
+                    (
+                        f["line"].as_u64().unwrap(),
+                        f["name"].as_str().unwrap().to_owned(),
+                    )
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn session() {
+        let path = std::env::temp_dir().join(format!("chip8-dap-{}.s", std::process::id()));
+        std::fs::write(&path, PROG).unwrap();
+
+        let (server_in, writer) = io::pipe().unwrap();
+        let (reader, server_out) = io::pipe().unwrap();
+        let server = std::thread::spawn(move || serve(server_in, server_out));
+        let mut client = Client {
+            writer,
+            reader: BufReader::new(reader),
+            seq: 0,
+            events: vec![],
+        };
+
+        let caps = client.request("initialize", json!({"adapterID": "chip8"}));
+        assert_eq!(caps["supportsReadMemoryRequest"], true);
+        client.request("launch", json!({"program": path, "seed": 1}));
+        client.event("initialized");
+
+        // the label's line moves to the instruction after it
+        let bps = client.request(
+            "setBreakpoints",
+            json!({"source": {"path": path}, "breakpoints": [{"line": 10}]}),
+        );
+        assert_eq!(bps["breakpoints"][0]["verified"], true);
+        assert_eq!(bps["breakpoints"][0]["line"], 11);
+
+        client.request("configurationDone", json!({}));
+        assert_eq!(client.event("stopped")["reason"], "breakpoint");
+        assert_eq!(
+            client.frames(),
+            [(11, "sub_20a".to_owned()), (5, "main".to_owned())]
+        );
+
+        client.request("next", json!({"threadId": 1}));
+        assert_eq!(client.event("stopped")["reason"], "step");
+        assert_eq!(client.frames()[0].0, 12);
+
+        client.request("stepOut", json!({"threadId": 1}));
+        assert_eq!(client.event("stopped")["reason"], "step");
+        assert_eq!(client.frames(), [(6, "main".to_owned())]);
+
+        let vars = client.request("variables", json!({"variablesReference": REGISTERS}));
+        assert_eq!(vars["variables"][0]["value"], "0x01");
+
+        client.request("disconnect", json!({}));
+        client.event("terminated");
+        server.join().unwrap().unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn read_memory_marks_what_is_past_the_end() {
+        let comp = Chip8::new();
+        let end = comp.mem().len() as i64;
+        let read = |reference: &str, offset: i64, count: u64| {
+            let args = json!({"memoryReference": reference, "offset": offset, "count": count});
+            read_memory(&comp, &args)
+        };
+
+        // the font's first row
+        let font = read("0x0", 0, 1).unwrap();
+        assert_eq!(font["address"], "0x000");
+        assert_eq!(font["data"], "8A==");
+        assert_eq!(font["unreadableBytes"], 0);
+
+        let tail = read(&format!("0x{:x}", end - 2), 0, 6).unwrap();
+        assert_eq!(tail["address"], format!("0x{:03x}", end - 2));
+        assert_eq!(tail["data"], "AAA=");
+        assert_eq!(tail["unreadableBytes"], 4);
+
+        let before = read("0x10", -0x20, 4).unwrap();
+        assert_eq!(before["address"], "-16");
+        assert_eq!(before["data"], "");
+        assert_eq!(before["unreadableBytes"], 4);
+
+        assert!(read(&i64::MAX.to_string(), 1, 1).is_err());
+    }
+}
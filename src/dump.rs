@@ -0,0 +1,313 @@
+//! Uncompressed video and audio of a run, every frame, for an encoder to
+//! turn into a proper recording, e.g.
+//! `chip8 headless ROM --movie run.movie --video - | ffmpeg -i - out.mp4`.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::frontend::AudioSink;
+use crate::palette::Palette;
+use crate::Chip8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoFormat {
+    /// Bare RGB24 frames one after another, the size has to be given to the
+    /// encoder.
+    Raw,
+    /// YUV4MPEG2, 4:4:4 with the size and frame rate in the header.
+    Y4m,
+}
+
+impl VideoFormat {
+    /// `.y4m` files are Y4M, anything else raw.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("y4m") => VideoFormat::Y4m,
+            _ => VideoFormat::Raw,
+        }
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" | "rgb" => Ok(VideoFormat::Raw),
+            "y4m" => Ok(VideoFormat::Y4m),
+            _ => Err(format!("Unknown video format {s}, expected raw or y4m")),
+        }
+    }
+}
+
+impl Display for VideoFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoFormat::Raw => write!(f, "raw"),
+            VideoFormat::Y4m => write!(f, "y4m"),
+        }
+    }
+}
+
+/// Writes the screen every frame at a fixed size, so high resolution
+/// frames get the same size as low resolution ones.
+pub struct VideoWriter {
+    out: Box<dyn Write>,
+    format: VideoFormat,
+    pub width: usize,
+    pub height: usize,
+    colors: [[u8; 3]; 4],
+    /// Palette indices of the frame being written.
+    frame: Vec<u8>,
+    /// The Y4M header went out.
+    started: bool,
+}
+
+impl VideoWriter {
+    /// Frames are `128 * scale` by `64 * scale`, the high resolution screen
+    /// with each pixel `scale` wide.
+    pub fn new(out: Box<dyn Write>, format: VideoFormat, palette: &Palette, scale: usize) -> Self {
+        let scale = scale.max(1);
+        VideoWriter {
+            out,
+            format,
+            width: 128 * scale,
+            height: 64 * scale,
+            colors: palette.rgb(),
+            frame: vec![],
+            started: false,
+        }
+    }
+
+    /// Opens `path`, or stdout for `-`.
+    pub fn open(path: &Path) -> Result<Box<dyn Write>, String> {
+        if path == Path::new("-") {
+            return Ok(Box::new(BufWriter::new(io::stdout())));
+        }
+        let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Box::new(BufWriter::new(file)))
+    }
+
+    pub fn write(&mut self, comp: &Chip8) -> Result<(), String> {
+        let (w, h) = (comp.width(), comp.height());
+        self.frame.clear();
+        for y in 0..self.height {
+            let row = &comp.gfx[y * h / self.height * w..][..w];
+            for x in 0..self.width {
+                self.frame.push(row[x * w / self.width] & 3);
+            }
+        }
+
+        let res = match self.format {
+            VideoFormat::Raw => {
+                let rgb: Vec<u8> = self
+                    .frame
+                    .iter()
+                    .flat_map(|p| self.colors[*p as usize])
+                    .collect();
+                self.out.write_all(&rgb)
+            }
+            VideoFormat::Y4m => self.write_y4m(),
+        };
+        res.map_err(|e| e.to_string())
+    }
+
+    fn write_y4m(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            writeln!(
+                self.out,
+                "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444",
+                self.width, self.height
+            )?;
+        }
+        self.out.write_all(b"FRAME\n")?;
+
+        // BT.601, limited range like most players expect
+        let planes: [fn(f32, f32, f32) -> f32; 3] = [
+            |r, g, b| 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0,
+            |r, g, b| 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0,
+            |r, g, b| 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0,
+        ];
+        for plane in planes {
+            let colors = self
+                .colors
+                .map(|[r, g, b]| plane(r as f32, g as f32, b as f32).round() as u8);
+            let bytes: Vec<u8> = self.frame.iter().map(|p| colors[*p as usize]).collect();
+            self.out.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(|e| e.to_string())
+    }
+}
+
+const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / 60;
+
+/// The beeper as a 16 bit mono WAV, the same square wave the SDL frontend
+/// plays. The sizes in the header are filled in by `finish`.
+pub struct WavWriter {
+    out: BufWriter<File>,
+    phase: f32,
+    samples: u32,
+    /// The first write that failed.
+    error: Option<String>,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let mut wav = WavWriter {
+            out: BufWriter::new(file),
+            phase: 0.0,
+            samples: 0,
+            error: None,
+        };
+        wav.header().map_err(|e| e.to_string())?;
+        Ok(wav)
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        let data_len = self.samples * 2;
+        self.out.write_all(b"RIFF")?;
+        self.out.write_all(&(36 + data_len).to_le_bytes())?;
+        self.out.write_all(b"WAVEfmt ")?;
+        self.out.write_all(&16u32.to_le_bytes())?;
+        // PCM, mono
+        self.out.write_all(&1u16.to_le_bytes())?;
+        self.out.write_all(&1u16.to_le_bytes())?;
+        self.out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        self.out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        self.out.write_all(&2u16.to_le_bytes())?;
+        self.out.write_all(&16u16.to_le_bytes())?;
+        self.out.write_all(b"data")?;
+        self.out.write_all(&data_len.to_le_bytes())
+    }
+
+    fn frame(&mut self, on: bool) -> io::Result<()> {
+        let volume = (0.1 * i16::MAX as f32) as i16;
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = match (on, self.phase <= 0.5) {
+                (false, _) => 0,
+                (true, true) => volume,
+                (true, false) => -volume,
+            };
+            self.out.write_all(&sample.to_le_bytes())?;
+            self.phase = (self.phase + 440.0 / SAMPLE_RATE as f32) % 1.0;
+        }
+        self.samples += SAMPLES_PER_FRAME;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let res = self
+            .out
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.header())
+            .and_then(|_| self.out.flush());
+        res.map_err(|e| e.to_string())
+    }
+}
+
+impl AudioSink for WavWriter {
+    /// Adds a frame's worth of samples. Write errors show up in `finish`.
+    fn set_tone(&mut self, on: bool) {
+        if let Err(e) = self.frame(on) {
+            self.error.get_or_insert(e.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// A writer the test can still read after handing it over.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn palette() -> Palette {
+        Palette::new("test", [0x000000, 0xffffff, 0xff0000, 0x0000ff])
+    }
+
+    #[test]
+    fn y4m_has_one_header_then_frames() {
+        let out = Shared::default();
+        let mut video = VideoWriter::new(Box::new(out.clone()), VideoFormat::Y4m, &palette(), 2);
+        let comp = Chip8::new();
+        video.write(&comp).unwrap();
+        video.write(&comp).unwrap();
+        video.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W256 H128 F60:1 Ip A1:1 C444\n";
+        let frame = 6 + 3 * 256 * 128;
+        let bytes = out.0.borrow();
+        assert_eq!(bytes.len(), header.len() + 2 * frame);
+        assert!(bytes.starts_with(header));
+        assert!(bytes[header.len()..].starts_with(b"FRAME\n"));
+        assert!(bytes[header.len() + frame..].starts_with(b"FRAME\n"));
+        // black is 16 in limited range luma
+        assert_eq!(bytes[header.len() + 6], 16);
+    }
+
+    #[test]
+    fn raw_frames_are_bare_rgb() {
+        let out = Shared::default();
+        let mut video = VideoWriter::new(Box::new(out.clone()), VideoFormat::Raw, &palette(), 1);
+        let mut comp = Chip8::new();
+        comp.gfx[0] = 1;
+        video.write(&comp).unwrap();
+
+        let bytes = out.0.borrow();
+        assert_eq!(bytes.len(), 3 * 128 * 64);
+        // a low resolution pixel is two wide
+        assert_eq!(bytes[..9], [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0]);
+    }
+
+    #[test]
+    fn wav_header_has_the_sizes_after_finish() {
+        let path = std::env::temp_dir().join(format!("chip8-test-{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(&path).unwrap();
+        wav.set_tone(true);
+        wav.set_tone(false);
+        wav.finish().unwrap();
+        drop(wav);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let data_len = 2 * 2 * SAMPLES_PER_FRAME;
+        assert_eq!(bytes.len(), 44 + data_len as usize);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(u32_at(4), 36 + data_len);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(24), SAMPLE_RATE);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(40), data_len);
+        // the tone starts high and the silence is zeros
+        assert!(i16::from_le_bytes([bytes[44], bytes[45]]) > 0);
+        assert!(bytes[44 + data_len as usize / 2..].iter().all(|b| *b == 0));
+    }
+}
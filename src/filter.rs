@@ -0,0 +1,200 @@
+//! CPU side filters run on successive framebuffers before they're shown.
+//! CHIP-8 games erase and redraw sprites with XOR, so a frame presented
+//! between the two shows the sprite missing. These hide that flicker.
+
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    /// Pixels are shown as they are.
+    None,
+    /// Lit pixels fade out over a number of frames instead of switching off.
+    Phosphor,
+    /// Each frame is averaged with the one before it.
+    Blend,
+    /// The screen is only shown at the end of a frame, never mid frame
+    /// after a single `Draw`.
+    VBlank,
+}
+
+impl FilterMode {
+    pub fn next(self) -> Self {
+        match self {
+            FilterMode::None => FilterMode::Phosphor,
+            FilterMode::Phosphor => FilterMode::Blend,
+            FilterMode::Blend => FilterMode::VBlank,
+            FilterMode::VBlank => FilterMode::None,
+        }
+    }
+}
+
+impl Display for FilterMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterMode::None => write!(f, "none"),
+            FilterMode::Phosphor => write!(f, "phosphor"),
+            FilterMode::Blend => write!(f, "blend"),
+            FilterMode::VBlank => write!(f, "vblank"),
+        }
+    }
+}
+
+impl std::str::FromStr for FilterMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FilterMode::None),
+            "phosphor" => Ok(FilterMode::Phosphor),
+            "blend" => Ok(FilterMode::Blend),
+            "vblank" => Ok(FilterMode::VBlank),
+            _ => Err(format!("Unknown filter {s}")),
+        }
+    }
+}
+
+/// Turns framebuffers into RGB24 frames, applying the current [`FilterMode`].
+pub struct FrameFilter {
+    pub mode: FilterMode,
+    /// Frames a pixel takes to fade out in `Phosphor` mode.
+    pub decay: u32,
+    prev: Vec<u8>,
+    glow: Vec<f32>,
+    out: Vec<u8>,
+    /// The last output blended in a previous frame that differs from the
+    /// current one.
+    blending: bool,
+}
+
+impl FrameFilter {
+    pub fn new(mode: FilterMode) -> Self {
+        FrameFilter {
+            mode,
+            decay: 8,
+            prev: vec![],
+            glow: vec![],
+            out: vec![],
+            blending: false,
+        }
+    }
+
+    pub fn with_decay(mut self, decay: u32) -> Self {
+        self.decay = decay.max(1);
+        self
+    }
+
+    /// Whether the output changes from frame to frame even when the
+    /// framebuffer doesn't, so it has to be presented every frame.
+    pub fn animating(&self) -> bool {
+        match self.mode {
+            FilterMode::Phosphor => true,
+            FilterMode::Blend => self.blending,
+            FilterMode::None | FilterMode::VBlank => false,
+        }
+    }
+
+    /// Colors `gfx` with `palette`, indexed by pixel value, and filters it
+    /// against the previous frames. Returns 3 bytes per pixel.
+    pub fn apply(&mut self, gfx: &[u8], palette: &[[u8; 3]]) -> &[u8] {
+        if self.prev.len() != gfx.len() {
+            self.prev = gfx.to_vec();
+            self.glow = gfx
+                .iter()
+                .flat_map(|p| color(palette, *p).map(|c| c as f32))
+                .collect();
+            self.out = vec![0; gfx.len() * 3];
+        }
+
+        let fade = 1.0 / self.decay as f32;
+        for (i, pixel) in gfx.iter().enumerate() {
+            let cur = color(palette, *pixel);
+            let px = &mut self.out[i * 3..i * 3 + 3];
+            match self.mode {
+                FilterMode::None | FilterMode::VBlank => px.copy_from_slice(&cur),
+                FilterMode::Blend => {
+                    let prev = color(palette, self.prev[i]);
+                    for c in 0..3 {
+                        px[c] = ((cur[c] as u16 + prev[c] as u16) / 2) as u8;
+                    }
+                }
+                FilterMode::Phosphor => {
+                    let glow = &mut self.glow[i * 3..i * 3 + 3];
+                    for c in 0..3 {
+                        // lit pixels light up at once, everything else
+                        // eases towards its color
+                        glow[c] = if *pixel != 0 {
+                            cur[c] as f32
+                        } else {
+                            glow[c] + (cur[c] as f32 - glow[c]) * fade
+                        };
+                        px[c] = glow[c].round() as u8;
+                    }
+                }
+            }
+        }
+
+        self.blending = self.mode == FilterMode::Blend && self.prev != gfx;
+        if self.mode != FilterMode::Phosphor {
+            for (glow, px) in self.glow.iter_mut().zip(&self.out) {
+                *glow = *px as f32;
+            }
+        }
+        self.prev.copy_from_slice(gfx);
+
+        &self.out
+    }
+}
+
+fn color(palette: &[[u8; 3]], pixel: u8) -> [u8; 3] {
+    palette[(pixel as usize).min(palette.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALETTE: [[u8; 3]; 2] = [[0, 0, 0], [200, 100, 40]];
+
+    /// Filters a frame with the first pixel lit, then one with it off.
+    fn lit_then_off(filter: &mut FrameFilter) -> (Vec<u8>, Vec<u8>) {
+        let lit = filter.apply(&[1, 0], &PALETTE).to_vec();
+        let off = filter.apply(&[0, 0], &PALETTE).to_vec();
+        (lit, off)
+    }
+
+    #[test]
+    fn none_and_vblank_show_pixels_as_they_are() {
+        for mode in [FilterMode::None, FilterMode::VBlank] {
+            let mut filter = FrameFilter::new(mode);
+            let (lit, off) = lit_then_off(&mut filter);
+            assert_eq!(lit, [200, 100, 40, 0, 0, 0]);
+            assert_eq!(off, [0; 6]);
+            assert!(!filter.animating());
+        }
+    }
+
+    #[test]
+    fn blend_averages_with_the_previous_frame() {
+        let mut filter = FrameFilter::new(FilterMode::Blend);
+        let (lit, off) = lit_then_off(&mut filter);
+        assert_eq!(lit, [200, 100, 40, 0, 0, 0]);
+        assert_eq!(off, [100, 50, 20, 0, 0, 0]);
+
+        // the same frame again blends the old one out
+        assert!(filter.animating());
+        assert_eq!(filter.apply(&[0, 0], &PALETTE), [0; 6]);
+        assert!(!filter.animating());
+    }
+
+    #[test]
+    fn phosphor_fades_pixels_out() {
+        let mut filter = FrameFilter::new(FilterMode::Phosphor).with_decay(4);
+        let (lit, off) = lit_then_off(&mut filter);
+        assert_eq!(lit, [200, 100, 40, 0, 0, 0]);
+        assert_eq!(off, [150, 75, 30, 0, 0, 0]);
+        assert!(filter.animating());
+
+        // lighting it again is immediate
+        assert_eq!(filter.apply(&[1, 0], &PALETTE), [200, 100, 40, 0, 0, 0]);
+    }
+}
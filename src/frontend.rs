@@ -0,0 +1,707 @@
+//! The pieces a frontend has to provide to drive a [`Chip8`]. SDL is one
+//! implementation (see the `sdl` feature), anything that can show a
+//! framebuffer and report keys can be another.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::filter::{FilterMode, FrameFilter};
+use crate::image::{self, Capture, Recording};
+use crate::keymap::{KeyMap, KEYPAD};
+use crate::movie::{Event, Movie};
+use crate::palette::Palette;
+use crate::{Chip8, CHIP8_FONTSET};
+
+pub enum Input {
+    /// A host key went down, by its [`KeyMap`] name.
+    Press(String),
+    Release(String),
+    /// A keypad key went down directly, bypassing the key map.
+    KeyDown(usize),
+    KeyUp(usize),
+    /// The display needs presenting again even though the screen didn't
+    /// change, e.g. after a window resize.
+    Redraw,
+    Fullscreen,
+    CycleFilter,
+    CyclePalette,
+    ToggleEffects,
+    /// Starts asking for a host key for each keypad key in turn.
+    BindKeys,
+    ToggleOverlay(Overlay),
+    /// Pauses or resumes emulation.
+    Pause,
+    /// Runs a single frame, pausing first if running.
+    AdvanceFrame,
+    /// Runs a single instruction, pausing first if running.
+    StepInstruction,
+    SpeedUp,
+    SpeedDown,
+    /// Toggles running frames as fast as possible.
+    FastForward,
+    /// Restarts the program, see [`Chip8::reset`].
+    Reset,
+    /// Saves the screen as a PNG, see [`Capture`].
+    Screenshot,
+    /// Starts recording the screen, or stops and saves the recording.
+    Record,
+    /// The primary mouse button or a finger went down or up at a position
+    /// in the window.
+    Pointer {
+        x: i32,
+        y: i32,
+        down: bool,
+    },
+    Quit,
+}
+
+/// Things a display can draw on top of the game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overlay {
+    /// The hex keypad, showing held keys, which can be clicked.
+    Keypad,
+    /// Speed and frame rate, see [`Status`].
+    Hud,
+    /// Registers, code and memory next to the game.
+    Debugger,
+    /// The sprites drawn so far, in the debugger instead of memory.
+    Sprites,
+}
+
+/// How the emulator is doing, for displays to show.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    /// Frames run in the last second.
+    pub fps: u32,
+    /// Instructions run in the last second.
+    pub ips: u32,
+    /// Multiplier of the tickrate, see [`SPEEDS`].
+    pub speed: f32,
+    pub paused: bool,
+    pub fast_forward: bool,
+    /// Frames are being recorded, see [`Input::Record`].
+    pub recording: bool,
+    /// A notice shown for a couple of seconds, like the new speed after
+    /// changing it.
+    pub message: Option<String>,
+    /// Why emulation stopped, it stays paused until reset.
+    pub error: Option<String>,
+}
+
+/// What a display gets to look at every frame for drawing overlays.
+pub struct FrameState<'a> {
+    pub comp: &'a Chip8,
+    pub keymap: &'a KeyMap,
+    pub status: &'a Status,
+}
+
+pub trait DisplaySink {
+    /// Shows a `width` x `height` frame of RGB24 pixels.
+    fn present(&mut self, frame: &[u8], width: usize, height: usize) -> Result<(), String>;
+
+    fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Turns any post processing the display does on or off.
+    fn toggle_effects(&mut self) {}
+
+    /// Called every frame before a possible `present`.
+    fn update(&mut self, _state: &FrameState) {}
+
+    /// Whether the display shows something that changes every frame, so
+    /// it has to be presented even when the screen didn't change.
+    fn animating(&self) -> bool {
+        false
+    }
+
+    fn toggle_overlay(&mut self, _overlay: Overlay) {}
+
+    /// Turns a click or touch at a window position into the keypad input
+    /// it hits, if any.
+    fn pointer(&mut self, _x: i32, _y: i32, _down: bool) -> Option<Input> {
+        None
+    }
+}
+
+pub trait AudioSink {
+    /// Turns the beeper on or off, called once per frame.
+    fn set_tone(&mut self, on: bool);
+}
+
+pub trait InputSource {
+    /// Returns the input events that happened since the last poll.
+    fn poll(&mut self) -> Result<Vec<Input>, String>;
+}
+
+/// How the framebuffer is fit into the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scaling {
+    /// Largest whole multiple of the screen size that fits, centered.
+    Integer,
+    /// Largest size that keeps the aspect ratio, letterboxed.
+    Fit,
+    /// Fills the window, ignoring the aspect ratio.
+    Stretch,
+}
+
+impl Scaling {
+    /// Where a `width` x `height` screen goes in a `win_w` x `win_h` window,
+    /// as x, y, width and height.
+    pub fn dest_rect(
+        self,
+        win_w: u32,
+        win_h: u32,
+        width: u32,
+        height: u32,
+    ) -> (i32, i32, u32, u32) {
+        let (w, h) = match self {
+            Scaling::Stretch => (win_w, win_h),
+            Scaling::Integer => {
+                let scale = (win_w / width).min(win_h / height).max(1);
+                (width * scale, height * scale)
+            }
+            Scaling::Fit => {
+                if win_w * height > win_h * width {
+                    (win_h * width / height, win_h)
+                } else {
+                    (win_w, win_w * height / width)
+                }
+            }
+        };
+
+        let x = (win_w as i32 - w as i32) / 2;
+        let y = (win_h as i32 - h as i32) / 2;
+        (x, y, w.max(1), h.max(1))
+    }
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            "stretch" => Ok(Scaling::Stretch),
+            _ => Err(format!("Unknown scaling {s}")),
+        }
+    }
+}
+
+/// Sink for frontends without sound.
+pub struct NoAudio;
+
+impl AudioSink for NoAudio {
+    fn set_tone(&mut self, _on: bool) {}
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Exit {
+    Halted,
+    Quit,
+}
+
+/// Multipliers of the tickrate [`Input::SpeedUp`] and [`Input::SpeedDown`]
+/// step through.
+pub const SPEEDS: [f32; 8] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 4.0, 8.0];
+
+/// Frames a [`Status::message`] stays up for.
+const MESSAGE_FRAMES: u32 = 120;
+
+/// What to run while paused.
+#[derive(Clone, Copy)]
+enum Advance {
+    Frame,
+    Instruction,
+}
+
+/// Run loop driving a [`Chip8`] with a display, audio and input implementation.
+pub struct Frontend<D, A, I> {
+    pub display: D,
+    pub audio: A,
+    pub input: I,
+    /// Instructions per frame at normal speed.
+    pub tickrate: u32,
+    /// Index into [`SPEEDS`].
+    pub speed: usize,
+    pub paused: bool,
+    /// Frames run without waiting for the next 60th of a second.
+    pub fast_forward: bool,
+    pub filter: FrameFilter,
+    pub palette: Palette,
+    /// Palettes cycled through with [`Input::CyclePalette`].
+    pub palettes: Vec<Palette>,
+    pub keymap: KeyMap,
+    /// Set once the bindings were changed with [`Input::BindKeys`].
+    pub keymap_changed: bool,
+    pub status: Status,
+    pub capture: Capture,
+    recording: Option<Recording>,
+    /// Records the input for playing the session back, see [`Movie`].
+    pub movie: Option<Movie>,
+    /// Start of the second `fps` and `ips` are being counted over, and the
+    /// frame and cycle counts at its start.
+    second: (Instant, u32, u32),
+    frames: u32,
+    message_frames: u32,
+    /// Index into [`KEYPAD`] of the key waiting for a binding.
+    binding: Option<usize>,
+    /// Host keys held down, with the keypad key each pressed. A keypad key
+    /// stays down until all of its host keys are released.
+    held: HashMap<String, usize>,
+    advance: Option<Advance>,
+    /// Keys held at the last frame, overlays show them so they redraw when
+    /// they change.
+    last_keys: u16,
+    redraw: bool,
+}
+
+impl<D: DisplaySink, A: AudioSink, I: InputSource> Frontend<D, A, I> {
+    pub fn new(display: D, audio: A, input: I) -> Self {
+        Frontend {
+            display,
+            audio,
+            input,
+            tickrate: 20,
+            speed: 3,
+            paused: false,
+            fast_forward: false,
+            filter: FrameFilter::new(FilterMode::None),
+            palette: Palette::default(),
+            palettes: Palette::presets(),
+            keymap: KeyMap::default(),
+            keymap_changed: false,
+            status: Status::default(),
+            capture: Capture::default(),
+            recording: None,
+            movie: None,
+            second: (Instant::now(), 0, 0),
+            frames: 0,
+            message_frames: 0,
+            binding: None,
+            held: HashMap::new(),
+            advance: None,
+            last_keys: 0,
+            redraw: false,
+        }
+    }
+
+    pub fn with_tickrate(mut self, tickrate: u32) -> Self {
+        self.tickrate = tickrate;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FrameFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn with_palettes(mut self, palettes: Vec<Palette>) -> Self {
+        self.palettes = palettes;
+        self
+    }
+
+    pub fn with_keymap(mut self, keymap: KeyMap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    pub fn with_capture(mut self, capture: Capture) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    /// Starts recording every frame run from now on.
+    pub fn with_recording(mut self, on: bool) -> Self {
+        self.recording = on.then(Recording::new);
+        self
+    }
+
+    pub fn with_movie(mut self, movie: Movie) -> Self {
+        self.movie = Some(movie);
+        self
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Saves the recording, if there is one, and says where it went or why
+    /// it couldn't be saved.
+    pub fn stop_recording(&mut self) -> Option<String> {
+        let recording = self.recording.take()?;
+        let path = self.capture.next_path(self.capture.format.extension());
+        Some(
+            match recording.save(&path, &self.palette, self.capture.scale) {
+                Ok(()) => format!("saved {} frames to {}", recording.frames(), path.display()),
+                Err(e) => format!("recording failed: {e}"),
+            },
+        )
+    }
+
+    fn screenshot(&mut self, comp: &Chip8) {
+        let path = self.capture.next_path("png");
+        let message = match image::screenshot(&path, comp, &self.palette, self.capture.scale) {
+            Ok(()) => format!("saved {}", path.display()),
+            Err(e) => format!("screenshot failed: {e}"),
+        };
+        self.notify(message);
+    }
+
+    /// Instructions per frame at the current speed.
+    pub fn ticks(&self) -> u32 {
+        ((self.tickrate as f32 * SPEEDS[self.speed]).round() as u32).max(1)
+    }
+
+    /// Shows `message` in the status for a while.
+    pub fn notify(&mut self, message: impl Into<String>) {
+        self.status.message = Some(message.into());
+        self.message_frames = MESSAGE_FRAMES;
+    }
+
+    fn update_status(&mut self, comp: &Chip8) {
+        let prev = self.status.clone();
+
+        self.frames += 1;
+        let (start, frames, cycles) = self.second;
+        let elapsed = start.elapsed().as_secs_f32();
+        if elapsed >= 1.0 {
+            self.status.fps = ((self.frames - frames) as f32 / elapsed).round() as u32;
+            self.status.ips = (comp.cycles.saturating_sub(cycles) as f32 / elapsed).round() as u32;
+            self.second = (Instant::now(), self.frames, comp.cycles);
+        }
+
+        if self.message_frames > 0 {
+            self.message_frames -= 1;
+            if self.message_frames == 0 {
+                self.status.message = None;
+            }
+        }
+
+        self.status.speed = SPEEDS[self.speed];
+        self.status.paused = self.paused;
+        self.status.fast_forward = self.fast_forward;
+        self.status.recording = self.recording.is_some();
+
+        if self.status != prev {
+            self.redraw = true;
+        }
+    }
+
+    /// Runs a single frame. Returns `Some` once the emulator should stop.
+    /// Emulation errors pause it and are shown in [`Status::error`].
+    pub fn frame(&mut self, comp: &mut Chip8) -> Result<Option<Exit>, String> {
+        if let Some(idx) = self.binding {
+            // the emulator is paused while binding keys
+            self.audio.set_tone(false);
+            if self.redraw {
+                let gfx = binding_screen(comp.width(), comp.height(), KEYPAD[idx]);
+                let frame = self.filter.apply(&gfx, &self.palette.rgb());
+                self.display.present(frame, comp.width(), comp.height())?;
+                self.redraw = false;
+            }
+        } else {
+            let stop_on_draw = self.filter.mode != FilterMode::VBlank;
+            let stepped = match (self.paused, self.advance.take()) {
+                (false, _) | (true, Some(Advance::Frame)) => {
+                    let ticks = self.ticks();
+                    if let Some(movie) = &mut self.movie {
+                        movie.settings(ticks, !stop_on_draw);
+                    }
+                    let stepped = comp.frame(ticks, stop_on_draw);
+                    if let Some(movie) = &mut self.movie {
+                        movie.next_frame();
+                    }
+                    if let Some(recording) = &mut self.recording {
+                        recording.push(comp);
+                    }
+                    stepped
+                }
+                (true, Some(Advance::Instruction)) => {
+                    self.record(Event::Step);
+                    // show every step, not just the ones that draw
+                    comp.draw = true;
+                    comp.step()
+                }
+                (true, None) => Ok(()),
+            };
+            if let Err(e) = stepped {
+                self.status.error = Some(format!("emu step fail: {} on cc {}", e, comp.cycles));
+                self.paused = true;
+            }
+
+            self.audio.set_tone(comp.sound_on() && !self.paused);
+
+            self.update_status(comp);
+            self.display.update(&FrameState {
+                comp,
+                keymap: &self.keymap,
+                status: &self.status,
+            });
+            if comp.keys() != self.last_keys {
+                self.last_keys = comp.keys();
+                self.redraw = true;
+            }
+
+            if comp.draw || self.redraw || self.filter.animating() || self.display.animating() {
+                let frame = self.filter.apply(&comp.gfx, &self.palette.rgb());
+                self.display.present(frame, comp.width(), comp.height())?;
+                comp.draw = false;
+                self.redraw = false;
+            }
+        }
+
+        for input in self.input.poll()? {
+            match input {
+                Input::Press(name) => match self.binding {
+                    Some(idx) => self.bind_next(idx, &name, comp),
+                    None => {
+                        if let Some(key) = self.keymap.get(&name) {
+                            let down = self.held.values().any(|k| *k == key);
+                            self.held.insert(name, key);
+                            if !down {
+                                self.key(comp, key, true);
+                            }
+                        }
+                    }
+                },
+                Input::Release(name) => {
+                    if let Some(key) = self.held.remove(&name) {
+                        if !self.held.values().any(|k| *k == key) {
+                            self.key(comp, key, false);
+                        }
+                    }
+                }
+                Input::KeyDown(key) => self.key(comp, key, true),
+                Input::KeyUp(key) => self.key(comp, key, false),
+                Input::Redraw => self.redraw = true,
+                Input::Fullscreen => {
+                    self.display.toggle_fullscreen()?;
+                    self.redraw = true;
+                }
+                Input::CycleFilter => {
+                    self.filter.mode = self.filter.mode.next();
+                    self.notify(format!("filter {}", self.filter.mode));
+                    self.redraw = true;
+                }
+                Input::CyclePalette => {
+                    self.cycle_palette();
+                    self.notify(format!("palette {}", self.palette));
+                    self.redraw = true;
+                }
+                Input::ToggleEffects => {
+                    self.display.toggle_effects();
+                    self.redraw = true;
+                }
+                Input::BindKeys => {
+                    self.binding = Some(0);
+                    self.redraw = true;
+                }
+                Input::ToggleOverlay(overlay) => {
+                    self.display.toggle_overlay(overlay);
+                    self.redraw = true;
+                }
+                // stepping on from an error would only hit it again
+                Input::Pause | Input::AdvanceFrame | Input::StepInstruction
+                    if self.status.error.is_some() =>
+                {
+                    self.notify("reset to continue");
+                }
+                Input::Pause => {
+                    self.paused = !self.paused;
+                    self.notify(if self.paused { "paused" } else { "resumed" });
+                }
+                Input::AdvanceFrame => {
+                    self.paused = true;
+                    self.advance = Some(Advance::Frame);
+                }
+                Input::StepInstruction => {
+                    self.paused = true;
+                    self.advance = Some(Advance::Instruction);
+                }
+                Input::SpeedUp | Input::SpeedDown => {
+                    self.speed = match input {
+                        Input::SpeedUp => (self.speed + 1).min(SPEEDS.len() - 1),
+                        _ => self.speed.saturating_sub(1),
+                    };
+                    self.notify(format!("speed {}x", SPEEDS[self.speed]));
+                }
+                Input::FastForward => {
+                    self.fast_forward = !self.fast_forward;
+                    self.notify(if self.fast_forward {
+                        "fast forward"
+                    } else {
+                        "normal speed"
+                    });
+                }
+                Input::Reset => {
+                    self.record(Event::Reset);
+                    comp.reset();
+                    self.status.error = None;
+                    self.paused = false;
+                    self.second = (Instant::now(), self.frames, 0);
+                    self.notify("reset");
+                }
+                Input::Screenshot => self.screenshot(comp),
+                Input::Record => match self.stop_recording() {
+                    Some(message) => self.notify(message),
+                    None => {
+                        self.recording = Some(Recording::new());
+                        self.notify("recording");
+                    }
+                },
+                Input::Pointer { x, y, down } => match self.display.pointer(x, y, down) {
+                    Some(Input::KeyDown(key)) => self.key(comp, key, true),
+                    Some(Input::KeyUp(key)) => self.key(comp, key, false),
+                    _ => {}
+                },
+                Input::Quit => return Ok(Some(Exit::Quit)),
+            }
+        }
+
+        if !comp.running {
+            return Ok(Some(Exit::Halted));
+        }
+
+        Ok(None)
+    }
+
+    /// Presses or releases a keypad key, noting it in the movie.
+    fn key(&mut self, comp: &mut Chip8, key: usize, down: bool) {
+        if down {
+            self.record(Event::Down(key));
+            comp.key_down(key);
+        } else {
+            self.record(Event::Up(key));
+            comp.key_up(key);
+        }
+    }
+
+    fn record(&mut self, event: Event) {
+        if let Some(movie) = &mut self.movie {
+            movie.record(event);
+        }
+    }
+
+    fn bind_next(&mut self, idx: usize, name: &str, comp: &mut Chip8) {
+        self.keymap.clear(KEYPAD[idx]);
+        self.keymap.bind(name, KEYPAD[idx]);
+        self.keymap_changed = true;
+        self.redraw = true;
+
+        self.binding = if idx + 1 < KEYPAD.len() {
+            Some(idx + 1)
+        } else {
+            // the screen was replaced by the prompt
+            comp.draw = true;
+            self.notify("keys bound");
+            None
+        };
+    }
+
+    fn cycle_palette(&mut self) {
+        if self.palettes.is_empty() {
+            return;
+        }
+
+        let next = match self
+            .palettes
+            .iter()
+            .position(|p| p.name == self.palette.name)
+        {
+            Some(idx) => (idx + 1) % self.palettes.len(),
+            None => 0,
+        };
+        self.palette = self.palettes[next].clone();
+    }
+
+    pub fn run(&mut self, comp: &mut Chip8) -> Result<Exit, String> {
+        loop {
+            if let Some(exit) = self.frame(comp)? {
+                self.audio.set_tone(false);
+                return Ok(exit);
+            }
+
+            // naively target 60 fps
+            if !self.fast_forward {
+                std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+            }
+        }
+    }
+}
+
+/// A framebuffer showing the keypad key waiting for a binding in the big
+/// font.
+fn binding_screen(width: usize, height: usize, key: usize) -> Vec<u8> {
+    let mut gfx = vec![0; width * height];
+    let scale = height / 8;
+    let x0 = (width - 4 * scale) / 2;
+    let y0 = (height - 5 * scale) / 2;
+
+    for (row, bits) in CHIP8_FONTSET[key * 5..key * 5 + 5].iter().enumerate() {
+        for col in 0..4 {
+            if bits & (0x80 >> col) == 0 {
+                continue;
+            }
+
+            for y in 0..scale {
+                let start = x0 + col * scale + (y0 + row * scale + y) * width;
+                gfx[start..start + scale].fill(1);
+            }
+        }
+    }
+
+    gfx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_scaling_centers_the_largest_whole_multiple() {
+        assert_eq!(
+            Scaling::Integer.dest_rect(1024, 512, 64, 32),
+            (0, 0, 1024, 512)
+        );
+        assert_eq!(
+            Scaling::Integer.dest_rect(1000, 600, 64, 32),
+            (20, 60, 960, 480)
+        );
+        // never smaller than 1x, even if that overflows the window
+        assert_eq!(
+            Scaling::Integer.dest_rect(32, 16, 64, 32),
+            (-16, -8, 64, 32)
+        );
+    }
+
+    #[test]
+    fn fit_scaling_keeps_the_aspect_ratio() {
+        // letterboxed top and bottom
+        assert_eq!(
+            Scaling::Fit.dest_rect(1000, 600, 64, 32),
+            (0, 50, 1000, 500)
+        );
+        // pillarboxed left and right
+        assert_eq!(
+            Scaling::Fit.dest_rect(1000, 400, 64, 32),
+            (100, 0, 800, 400)
+        );
+    }
+
+    #[test]
+    fn stretch_scaling_fills_the_window() {
+        assert_eq!(
+            Scaling::Stretch.dest_rect(1000, 600, 64, 32),
+            (0, 0, 1000, 600)
+        );
+        assert_eq!(Scaling::Stretch.dest_rect(0, 0, 64, 32), (0, 0, 1, 1));
+    }
+}
@@ -0,0 +1,563 @@
+//! A stub for GDB's remote serial protocol, so debuggers that speak it can
+//! read and write registers and memory, set breakpoints and step a ROM
+//! running without a display.
+//!
+//! The registers are V0 to VF, `I`, `pc`, `sp` and the delay and sound
+//! timers, numbered 0 to 20 and sent big-endian like the machine's memory;
+//! `qXfer:features:read` hands out the matching target description.
+//! Addresses written to `I`, `pc` and `sp` are clamped to memory, and an
+//! instruction that reaches past it stops with SIGILL. While continuing
+//! the ROM runs at the tickrate, 60 frames a second, until a breakpoint or
+//! an interrupt from the debugger.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::Chip8;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.cpu">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// Bytes each register takes, in register number order.
+const REG_SIZES: [usize; 21] = [
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1,
+];
+
+/// Why the machine stopped, as a reply to the debugger.
+enum Stop {
+    /// SIGTRAP, a breakpoint or a finished step.
+    Trap,
+    /// SIGINT, the debugger interrupted it.
+    Interrupt,
+    /// SIGILL, an instruction failed.
+    Fault,
+    /// The program ran `Halt`.
+    Exited,
+    /// The debugger went away while it ran.
+    Disconnected,
+}
+
+impl Stop {
+    fn reply(&self) -> &'static str {
+        match self {
+            Stop::Trap => "S05",
+            Stop::Interrupt => "S02",
+            Stop::Fault => "S04",
+            Stop::Exited => "W00",
+            Stop::Disconnected => "",
+        }
+    }
+}
+
+/// Waits on localhost `port` for a debugger and serves it until it
+/// detaches, kills the program or disconnects.
+pub fn serve(comp: &mut Chip8, tickrate: u32, port: u16) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    eprintln!("Waiting for gdb on localhost:{port}");
+    serve_listener(comp, tickrate, listener)
+}
+
+/// Like [`serve`] on a listener that's already bound.
+pub fn serve_listener(
+    comp: &mut Chip8,
+    tickrate: u32,
+    listener: TcpListener,
+) -> Result<(), String> {
+    let (stream, peer) = listener.accept().map_err(|e| e.to_string())?;
+    eprintln!("Debugger connected from {peer}");
+    // packets are small and each waits on an ack
+    stream.set_nodelay(true).map_err(|e| e.to_string())?;
+
+    let mut stub = Stub {
+        comp,
+        tickrate,
+        breakpoints: BTreeSet::new(),
+        reader: BufReader::new(stream.try_clone().map_err(|e| e.to_string())?),
+        stream,
+        ack: true,
+    };
+    stub.run().map_err(|e| e.to_string())
+}
+
+struct Stub<'a> {
+    comp: &'a mut Chip8,
+    tickrate: u32,
+    breakpoints: BTreeSet<usize>,
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+    /// Whether packets are still acknowledged, until `QStartNoAckMode`.
+    ack: bool,
+}
+
+impl Stub<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = match packet.as_str() {
+                "?" => Stop::Trap.reply().to_owned(),
+                // an interrupt while already stopped
+                "\x03" => Stop::Interrupt.reply().to_owned(),
+                "k" => return Ok(()),
+                "D" => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                "g" => self.read_registers(),
+                "qAttached" => "1".into(),
+                "qC" => "QC1".into(),
+                "qfThreadInfo" => "m1".into(),
+                "qsThreadInfo" => "l".into(),
+                "QStartNoAckMode" => {
+                    self.send("OK")?;
+                    self.ack = false;
+                    continue;
+                }
+                p if p.starts_with("qSupported") => {
+                    "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".into()
+                }
+                p if p.starts_with("qXfer:features:read:target.xml:") => {
+                    self.target_xml(&p["qXfer:features:read:target.xml:".len()..])
+                }
+                p if p.starts_with('H') => "OK".into(),
+                p if p.starts_with('G') => self.write_registers(&p[1..]),
+                p if p.starts_with('p') => self.read_register(&p[1..]),
+                p if p.starts_with('P') => self.write_register(&p[1..]),
+                p if p.starts_with('m') => self.read_memory(&p[1..]),
+                p if p.starts_with('M') => self.write_memory(&p[1..]),
+                p if p.starts_with("Z0,") || p.starts_with("Z1,") => self.breakpoint(&p[3..], true),
+                p if p.starts_with("z0,") || p.starts_with("z1,") => {
+                    self.breakpoint(&p[3..], false)
+                }
+                p if p.starts_with('s') || p.starts_with('c') => {
+                    if let Some(addr) = parse_hex(&p[1..]) {
+                        self.comp.set_pc(self.clamp(addr));
+                    }
+                    let stop = match p.starts_with('s') {
+                        true => self.step(),
+                        false => self.resume()?,
+                    };
+                    match stop {
+                        Stop::Disconnected => return Ok(()),
+                        Stop::Exited => {
+                            self.send(stop.reply())?;
+                            return Ok(());
+                        }
+                        stop => stop.reply().to_owned(),
+                    }
+                }
+                // anything else isn't supported
+                _ => String::new(),
+            };
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    /// The next packet's contents, or `None` once the debugger is gone.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            match byte[0] {
+                b'$' => {}
+                0x03 => return Ok(Some("\x03".into())),
+                // acks, and anything between packets
+                _ => continue,
+            }
+
+            // the checksum covers the bytes as sent, escapes included
+            let mut data = vec![];
+            let mut sum = 0u8;
+            let mut escaped = false;
+            loop {
+                self.reader.read_exact(&mut byte)?;
+                match byte[0] {
+                    b'#' => break,
+                    b'}' if !escaped => escaped = true,
+                    b if escaped => {
+                        data.push(b ^ 0x20);
+                        escaped = false;
+                    }
+                    b => data.push(b),
+                }
+                sum = sum.wrapping_add(byte[0]);
+            }
+            let mut expected = [0; 2];
+            self.reader.read_exact(&mut expected)?;
+
+            if self.ack {
+                let ok = std::str::from_utf8(&expected)
+                    .ok()
+                    .and_then(|s| u8::from_str_radix(s, 16).ok())
+                    == Some(sum);
+                self.stream.write_all(if ok { b"+" } else { b"-" })?;
+                if !ok {
+                    continue;
+                }
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let data = escape(data.as_bytes());
+        let sum = data.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+        let mut packet = vec![b'$'];
+        packet.extend(&data);
+        packet.extend(format!("#{sum:02x}").bytes());
+        self.stream.write_all(&packet)?;
+
+        if self.ack {
+            // wait for the debugger's + and send it again on a -
+            let mut byte = [0];
+            loop {
+                if self.reader.read(&mut byte)? == 0 {
+                    return Ok(());
+                }
+                match byte[0] {
+                    b'+' => return Ok(()),
+                    b'-' => self.stream.write_all(&packet)?,
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn register(&self, n: usize) -> Option<u16> {
+        let comp = &self.comp;
+        Some(match n {
+            0..=15 => comp.regs()[n] as u16,
+            16 => comp.index() as u16,
+            17 => comp.pc() as u16,
+            18 => comp.sp() as u16,
+            19 => comp.delay_timer() as u16,
+            20 => comp.sound_timer() as u16,
+            _ => return None,
+        })
+    }
+
+    /// `addr` moved into memory, for the registers that point into it.
+    fn clamp(&self, addr: usize) -> usize {
+        addr.min(self.comp.mem().len() - 1)
+    }
+
+    fn set_register(&mut self, n: usize, val: u16) {
+        let addr = self.clamp(val as usize);
+        let comp = &mut self.comp;
+        match n {
+            0..=15 => comp.set_reg(n, val as u8),
+            16 => comp.set_index(addr),
+            17 => comp.set_pc(addr),
+            18 => comp.set_sp(addr),
+            19 => comp.set_delay_timer(val as u8),
+            20 => comp.set_sound_timer(val as u8),
+            _ => {}
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REG_SIZES.len())
+            .map(|n| hex_reg(self.register(n).unwrap_or(0), REG_SIZES[n]))
+            .collect()
+    }
+
+    fn write_registers(&mut self, hex: &str) -> String {
+        let Some(bytes) = from_hex(hex) else {
+            return "E01".into();
+        };
+        let mut bytes = bytes.iter();
+        for (n, size) in REG_SIZES.iter().enumerate() {
+            let val = bytes
+                .by_ref()
+                .take(*size)
+                .fold(0u16, |a, b| a << 8 | *b as u16);
+            self.set_register(n, val);
+        }
+        "OK".into()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        match parse_hex(args).and_then(|n| Some((self.register(n)?, REG_SIZES[n]))) {
+            Some((val, size)) => hex_reg(val, size),
+            None => "E01".into(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let parsed = args.split_once('=').and_then(|(n, val)| {
+            let n = parse_hex(n).filter(|n| *n < REG_SIZES.len())?;
+            let val = from_hex(val)?.iter().fold(0u16, |a, b| a << 8 | *b as u16);
+            Some((n, val))
+        });
+        match parsed {
+            Some((n, val)) => {
+                self.set_register(n, val);
+                "OK".into()
+            }
+            None => "E01".into(),
+        }
+    }
+
+    /// `addr,len` of memory, or an error past the end of it.
+    fn memory_range(&self, args: &str) -> Option<(usize, usize)> {
+        let (addr, len) = args.split_once(',')?;
+        let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+        (addr.checked_add(len)? <= self.comp.mem().len()).then_some((addr, len))
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        match self.memory_range(args) {
+            Some((addr, len)) => to_hex(&self.comp.mem()[addr..addr + len]),
+            None => "E01".into(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let parsed = args.split_once(':').and_then(|(range, data)| {
+            let (addr, len) = self.memory_range(range)?;
+            Some((addr, from_hex(data).filter(|d| d.len() == len)?))
+        });
+        match parsed {
+            Some((addr, data)) => {
+                self.comp.mem_mut()[addr..addr + data.len()].copy_from_slice(&data);
+                "OK".into()
+            }
+            None => "E01".into(),
+        }
+    }
+
+    /// Sets or clears the breakpoint in `addr,kind`.
+    fn breakpoint(&mut self, args: &str, set: bool) -> String {
+        let Some(addr) = args.split(',').next().and_then(parse_hex) else {
+            return "E01".into();
+        };
+        match set {
+            true => self.breakpoints.insert(addr),
+            false => self.breakpoints.remove(&addr),
+        };
+        "OK".into()
+    }
+
+    fn step(&mut self) -> Stop {
+        if !self.comp.running {
+            return Stop::Exited;
+        }
+        match self.comp.step() {
+            Ok(()) if !self.comp.running => Stop::Exited,
+            Ok(()) => Stop::Trap,
+            Err(e) => {
+                eprintln!("{e}");
+                Stop::Fault
+            }
+        }
+    }
+
+    /// Runs until a breakpoint, checking for an interrupt every frame. The
+    /// instruction at `pc` runs first even if it has a breakpoint, so
+    /// continuing from one moves on.
+    fn resume(&mut self) -> io::Result<Stop> {
+        let frame = Duration::from_secs(1) / 60;
+        let mut next = Instant::now();
+        let mut first = true;
+        loop {
+            for _ in 0..self.tickrate {
+                if !first && self.breakpoints.contains(&self.comp.pc()) {
+                    return Ok(Stop::Trap);
+                }
+                first = false;
+                match self.step() {
+                    Stop::Trap => {}
+                    stop => return Ok(stop),
+                }
+            }
+            self.comp.dec_timers();
+
+            self.stream.set_nonblocking(true)?;
+            let mut byte = [0];
+            let read = self.reader.read(&mut byte);
+            self.stream.set_nonblocking(false)?;
+            match read {
+                Ok(0) => return Ok(Stop::Disconnected),
+                Ok(_) if byte[0] == 0x03 => return Ok(Stop::Interrupt),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+
+            next += frame;
+            std::thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    /// The `offset,length` part of the target description.
+    fn target_xml(&self, args: &str) -> String {
+        let range = args
+            .split_once(',')
+            .and_then(|(off, len)| Some((parse_hex(off)?, parse_hex(len)?)));
+        let Some((off, len)) = range else {
+            return "E01".into();
+        };
+        let rest = TARGET_XML.get(off.min(TARGET_XML.len())..).unwrap_or("");
+        match rest.len() > len {
+            true => format!("m{}", &rest[..len]),
+            false => format!("l{rest}"),
+        }
+    }
+}
+
+/// Escapes the bytes the protocol uses for framing.
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for b in data {
+        match b {
+            b'#' | b'$' | b'}' | b'*' => out.extend([b'}', b ^ 0x20]),
+            b => out.push(*b),
+        }
+    }
+    out
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `val` as `size` big-endian bytes in hex.
+fn hex_reg(val: u16, size: usize) -> String {
+    to_hex(&val.to_be_bytes()[2 - size..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A debugger's end of the connection, acknowledging every reply.
+    struct Client {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            let sum = data.bytes().fold(0u8, |a, b| a.wrapping_add(b));
+            write!(self.stream, "${data}#{sum:02x}").unwrap();
+
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], b'+', "ack for {data}");
+            loop {
+                self.reader.read_exact(&mut byte).unwrap();
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+            let mut reply = vec![];
+            loop {
+                self.reader.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut sum = [0; 2];
+            self.reader.read_exact(&mut sum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    #[test]
+    fn session() {
+        // v0 = 5, then v0 += 1 forever
+        let mut comp = Chip8::new().with_seed(0);
+        comp.load(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]).unwrap();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || serve_listener(&mut comp, 20, listener));
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut client = Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            stream,
+        };
+
+        assert!(client
+            .request("qSupported:xmlRegisters=i386")
+            .contains("qXfer:features:read+"));
+        let xml = client.request("qXfer:features:read:target.xml:0,fff");
+        assert!(xml.starts_with("l<?xml"));
+        assert!(xml.contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+
+        // 16 one byte registers, I, pc and sp in two, then the timers
+        let regs = client.request("g");
+        assert_eq!(regs.len(), (16 + 3 * 2 + 2) * 2);
+        assert_eq!(&regs[32..44], "000002000ea0");
+
+        assert_eq!(client.request("m200,2"), "6005");
+        assert_eq!(client.request("M300,2:abcd"), "OK");
+        assert_eq!(client.request("m300,2"), "abcd");
+
+        assert_eq!(client.request("Z0,204,2"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p11"), "0204");
+        assert_eq!(client.request("p0"), "06");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p11"), "0202");
+
+        // I clamped to the last byte, BCD there runs off the end
+        assert_eq!(client.request("P10=ffff"), "OK");
+        assert_eq!(client.request("p10"), "0fff");
+        assert_eq!(client.request("M200,2:f033"), "OK");
+        assert_eq!(client.request("s200"), "S04");
+        assert_eq!(client.request("mffffffffffffffff,2"), "E01");
+        assert_eq!(client.request("Mfff,2:0000"), "E01");
+
+        assert_eq!(client.request("D"), "OK");
+        server.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+//! Writing pictures out to files: screenshots of the screen and
+//! recordings of it as animated GIF or PNG.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::palette::Palette;
+use crate::Chip8;
+
+/// Saves `rgb`, three bytes a pixel row by row, as a PNG.
+pub fn save_png(path: &Path, rgb: &[u8], width: usize, height: usize) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(rgb).map_err(|e| e.to_string())
+}
+
+/// Saves the screen as a PNG, each pixel a `scale` sided square.
+pub fn screenshot(
+    path: &Path,
+    comp: &Chip8,
+    palette: &Palette,
+    scale: usize,
+) -> Result<(), String> {
+    let scale = scale.max(1);
+    let colors = palette.rgb();
+    let rgb: Vec<u8> = scaled(&comp.gfx, comp.width(), scale)
+        .iter()
+        .flat_map(|p| colors[*p as usize & 3])
+        .collect();
+    save_png(path, &rgb, comp.width() * scale, comp.height() * scale)
+}
+
+/// `gfx` with each pixel repeated `scale` times across and down.
+fn scaled(gfx: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(gfx.len() * scale * scale);
+    for row in gfx.chunks(width) {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|p| std::iter::repeat_n(*p, scale))
+            .collect();
+        for _ in 0..scale {
+            out.extend_from_slice(&line);
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    /// Played back at 50 fps, the fastest browsers keep to.
+    Gif,
+    /// Animated PNG, all 60 frames a second.
+    Apng,
+}
+
+impl RecordFormat {
+    /// The format a file name asks for, `.gif`, `.png` or `.apng`.
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.parse(),
+            None => Err(format!("{}: expected a .gif or .png file", path.display())),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordFormat::Gif => "gif",
+            RecordFormat::Apng => "png",
+        }
+    }
+}
+
+impl FromStr for RecordFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gif" => Ok(RecordFormat::Gif),
+            "apng" | "png" => Ok(RecordFormat::Apng),
+            _ => Err(format!(
+                "Unknown recording format {s}, expected gif or apng"
+            )),
+        }
+    }
+}
+
+impl Display for RecordFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordFormat::Gif => write!(f, "gif"),
+            RecordFormat::Apng => write!(f, "apng"),
+        }
+    }
+}
+
+/// Frames of the screen, one per 60th of a second, kept as they are until
+/// `save` encodes them with a palette.
+#[derive(Default)]
+pub struct Recording {
+    /// Screen width, pixels and how many frames in a row they stayed.
+    runs: Vec<(usize, Vec<u8>, u32)>,
+    frames: u32,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Recording::default()
+    }
+
+    /// Adds the screen as the next frame.
+    pub fn push(&mut self, comp: &Chip8) {
+        self.frames += 1;
+        if let Some((width, gfx, count)) = self.runs.last_mut() {
+            if *width == comp.width() && *gfx == comp.gfx {
+                *count += 1;
+                return;
+            }
+        }
+        self.runs.push((comp.width(), comp.gfx.clone(), 1));
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Encodes the frames at `scale` in the format the file name asks for.
+    /// Low resolution frames are doubled if the game switched to high
+    /// resolution during the recording.
+    pub fn save(&self, path: &Path, palette: &Palette, scale: usize) -> Result<(), String> {
+        let format = RecordFormat::from_path(path)?;
+        if self.runs.is_empty() {
+            return Err("Nothing was recorded".into());
+        }
+
+        let screen_w = self.runs.iter().map(|(w, _, _)| *w).max().unwrap_or(0);
+        let scale = scale.max(1);
+        let frames: Vec<(Vec<u8>, u32)> = self
+            .runs
+            .iter()
+            .map(|(w, gfx, count)| (scaled(gfx, *w, scale * screen_w / w), *count))
+            .collect();
+        let (width, height) = (screen_w * scale, frames[0].0.len() / (screen_w * scale));
+        let colors: Vec<u8> = palette.rgb().concat();
+
+        let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let out = BufWriter::new(file);
+        match format {
+            RecordFormat::Gif => write_gif(out, &frames, width, height, &colors),
+            RecordFormat::Apng => write_apng(out, &frames, width, height, &colors),
+        }
+    }
+}
+
+fn write_gif(
+    out: BufWriter<File>,
+    frames: &[(Vec<u8>, u32)],
+    width: usize,
+    height: usize,
+    colors: &[u8],
+) -> Result<(), String> {
+    let (Ok(gif_w), Ok(gif_h)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(format!(
+            "{width}x{height} is too big for a GIF, which is at most 65535x65535"
+        ));
+    };
+    let mut encoder = gif::Encoder::new(out, gif_w, gif_h, colors).map_err(|e| e.to_string())?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|e| e.to_string())?;
+
+    // delays are in hundredths of a second and browsers slow anything
+    // under 2 down, so frames are picked at 50 fps: tick k shows frame k * 6 / 5
+    let mut start = 0;
+    for (pixels, count) in frames {
+        let end = start + count;
+        let ticks = (end * 5).div_ceil(6) - (start * 5).div_ceil(6);
+        start = end;
+        if ticks == 0 {
+            continue;
+        }
+
+        let mut frame = gif::Frame::from_indexed_pixels(gif_w, gif_h, pixels.as_slice(), None);
+        frame.delay = (ticks * 2).min(u16::MAX as u32) as u16;
+        encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn write_apng(
+    out: BufWriter<File>,
+    frames: &[(Vec<u8>, u32)],
+    width: usize,
+    height: usize,
+    colors: &[u8],
+) -> Result<(), String> {
+    let (Ok(png_w), Ok(png_h)) = (u32::try_from(width), u32::try_from(height)) else {
+        return Err(format!("{width}x{height} is too big for a PNG"));
+    };
+    let mut encoder = png::Encoder::new(out, png_w, png_h);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(colors);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(|e| e.to_string())?;
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    for (pixels, count) in frames {
+        writer
+            .set_frame_delay((*count).min(u16::MAX as u32) as u16, 60)
+            .map_err(|e| e.to_string())?;
+        writer.write_image_data(pixels).map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())
+}
+
+/// Where screenshots and recordings made while running go.
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub dir: PathBuf,
+    /// Start of the file names, usually the ROM's.
+    pub name: String,
+    /// Size of a screen pixel.
+    pub scale: usize,
+    pub format: RecordFormat,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture {
+            dir: PathBuf::from("."),
+            name: "chip8".into(),
+            scale: 8,
+            format: RecordFormat::Gif,
+        }
+    }
+}
+
+impl Capture {
+    /// The first `<name>-<n>.<ext>` that doesn't exist yet.
+    pub fn next_path(&self, ext: &str) -> PathBuf {
+        (1..)
+            .map(|n| self.dir.join(format!("{}-{n}.{ext}", self.name)))
+            .find(|path| !path.exists())
+            .unwrap()
+    }
+}
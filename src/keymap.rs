@@ -0,0 +1,207 @@
+//! Bindings from host keys to the 16 key hex keypad.
+//!
+//! Host keys are named the same way by every frontend: printable keys by
+//! their lowercase character (`"q"`, `"1"`, `","`) and everything else by
+//! a name like `"up"`, `"space"` or `"enter"`. Game controller buttons are
+//! `"pad_up"`, `"pad_a"` and so on.
+
+use std::collections::BTreeMap;
+
+/// Keypad keys in the order they're laid out on the COSMAC VIP
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+pub const KEYPAD: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xc, 0x4, 0x5, 0x6, 0xd, 0x7, 0x8, 0x9, 0xe, 0xa, 0x0, 0xb, 0xf,
+];
+
+/// The 4x4 block of keys under the number row for each layout, in
+/// [`KEYPAD`] order, so the keypad sits in the same physical place.
+const LAYOUTS: [(&str, &str); 4] = [
+    ("qwerty", "1234qwerasdfzxcv"),
+    ("qwertz", "1234qwerasdfyxcv"),
+    ("azerty", "1234azerqsdfwxcv"),
+    ("dvorak", "1234',.paoeu;qjk"),
+];
+
+/// Controller bindings every layout starts with. The d-pad is on 5/7/8/9,
+/// the directions most games use, the face buttons on the keys around it.
+const PAD: [(&str, usize); 10] = [
+    ("pad_up", 0x5),
+    ("pad_down", 0x8),
+    ("pad_left", 0x7),
+    ("pad_right", 0x9),
+    ("pad_a", 0x6),
+    ("pad_b", 0x4),
+    ("pad_x", 0xa),
+    ("pad_y", 0xb),
+    ("pad_start", 0xf),
+    ("pad_back", 0x0),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap {
+    bindings: BTreeMap<String, usize>,
+}
+
+impl KeyMap {
+    pub fn empty() -> Self {
+        KeyMap {
+            bindings: BTreeMap::new(),
+        }
+    }
+
+    pub fn layout(name: &str) -> Option<KeyMap> {
+        let (_, keys) = LAYOUTS.iter().find(|(n, _)| *n == name)?;
+        let mut map = KeyMap::empty();
+        for (c, key) in keys.chars().zip(KEYPAD) {
+            map.bind(&c.to_string(), key);
+        }
+
+        for (name, key) in PAD {
+            map.bind(name, key);
+        }
+
+        // the number row on azerty needs shift for digits
+        if name == "azerty" {
+            for (c, key) in "&é\"'".chars().zip(KEYPAD) {
+                map.bind(&c.to_string(), key);
+            }
+        }
+
+        Some(map)
+    }
+
+    pub fn layouts() -> impl Iterator<Item = &'static str> {
+        LAYOUTS.iter().map(|(name, _)| *name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.bindings.get(&name.to_lowercase()).copied()
+    }
+
+    /// Adds `name` as another host key for `key`.
+    pub fn bind(&mut self, name: &str, key: usize) {
+        self.bindings.insert(name.to_lowercase(), key & 0xf);
+    }
+
+    /// Removes every host key bound to `key`.
+    pub fn clear(&mut self, key: usize) {
+        self.bindings.retain(|_, k| *k != key);
+    }
+
+    /// Replaces the bindings of each keypad key in `keys`, a table of hex
+    /// digit to host key names as found in the config file.
+    pub fn apply(&mut self, keys: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+        for (key, names) in keys {
+            let key = parse_key(key)?;
+            self.clear(key);
+            for name in names {
+                self.bind(name, key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Like [`KeyMap::apply`] but keeps the keys' other bindings.
+    pub fn extend(&mut self, keys: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+        for (key, names) in keys {
+            let key = parse_key(key)?;
+            for name in names {
+                self.bind(name, key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Host keys bound to `key`.
+    pub fn names(&self, key: usize) -> Vec<&str> {
+        self.bindings
+            .iter()
+            .filter(|(_, k)| **k == key)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// The bindings as a `[keys]` table for the config file.
+    pub fn to_toml(&self) -> String {
+        let mut out = String::from("[keys]\n");
+        for key in 0..16 {
+            let names: Vec<String> = self.names(key).iter().map(|n| format!("{n:?}")).collect();
+            out.push_str(&format!("{key:x} = [{}]\n", names.join(", ")));
+        }
+        out
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::layout("qwerty").unwrap()
+    }
+}
+
+fn parse_key(key: &str) -> Result<usize, String> {
+    usize::from_str_radix(key, 16)
+        .ok()
+        .filter(|k| *k < 16)
+        .ok_or_else(|| format!("Invalid keypad key {key:?}, expected 0-f"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_put_the_keypad_in_the_same_place() {
+        let qwerty = KeyMap::layout("qwerty").unwrap();
+        let azerty = KeyMap::layout("azerty").unwrap();
+        assert_eq!(qwerty.get("q"), Some(0x4));
+        assert_eq!(azerty.get("a"), Some(0x4));
+        assert_eq!(qwerty.get("z"), Some(0xa));
+        assert_eq!(azerty.get("w"), Some(0xa));
+        // and the shifted number row
+        assert_eq!(azerty.get("é"), Some(0x2));
+        assert!(KeyMap::layout("colemak").is_none());
+    }
+
+    #[test]
+    fn get_ignores_case() {
+        let keymap = KeyMap::default();
+        assert_eq!(keymap.get("Q"), keymap.get("q"));
+    }
+
+    #[test]
+    fn apply_replaces_each_listed_key() {
+        let mut keymap = KeyMap::default();
+        let keys = BTreeMap::from([("5".to_owned(), vec!["up".to_owned(), "K".to_owned()])]);
+        keymap.apply(&keys).unwrap();
+
+        assert_eq!(keymap.get("w"), None);
+        assert_eq!(keymap.names(0x5), ["k", "up"]);
+        // other keys keep their bindings
+        assert_eq!(keymap.get("q"), Some(0x4));
+    }
+
+    #[test]
+    fn extend_keeps_the_other_bindings() {
+        let mut keymap = KeyMap::default();
+        let keys = BTreeMap::from([("5".to_owned(), vec!["up".to_owned()])]);
+        keymap.extend(&keys).unwrap();
+        assert_eq!(keymap.names(0x5), ["pad_up", "up", "w"]);
+    }
+
+    #[test]
+    fn apply_and_extend_reject_keys_outside_the_keypad() {
+        for key in ["10", "g", ""] {
+            let keys = BTreeMap::from([(key.to_owned(), vec!["up".to_owned()])]);
+            assert!(KeyMap::default().apply(&keys).is_err(), "{key:?} applied");
+            assert!(KeyMap::default().extend(&keys).is_err(), "{key:?} extended");
+        }
+    }
+}
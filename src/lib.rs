@@ -1,18 +1,54 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
 
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use coverage::Coverage;
+use profile::Profile;
+use sprites::SpriteSize;
+
+pub mod asm;
+pub mod config;
+pub mod coverage;
+pub mod crt;
+pub mod dap;
+pub mod dump;
+pub mod filter;
+pub mod font;
+pub mod frontend;
+pub mod gdb;
+pub mod image;
+pub mod keymap;
+pub mod movie;
+pub mod palette;
+pub mod profile;
+pub mod romdb;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod sprites;
+#[cfg(feature = "tui")]
+pub mod tui;
 
 const MEM_SIZE: usize = 4096;
-const PROG_OFFSET: usize = 512;
+/// Where programs are loaded and start running.
+pub const PROG_OFFSET: usize = 512;
 const DISP_OFFSET: usize = MEM_SIZE - 256;
 const INT_OFFSET: usize = DISP_OFFSET - 96;
 
-const GFX_SIZE: usize = 64 * 32;
+const GFX_WIDTH: usize = 64;
+const GFX_HEIGHT: usize = 32;
+const HIRES_WIDTH: usize = 128;
+const HIRES_HEIGHT: usize = 64;
 
 const FLAG_REG: usize = 15; // 0x0f
 
-const CHIP8_FONTSET: [u8; 80] = [
+/// Hex digit glyphs, 5 bytes each, loaded at address 0.
+pub const CHIP8_FONTSET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -53,11 +89,13 @@ impl Cpu {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum OpCode {
     NativeCall(Addr),       // 0NNN
     DispClear,              // 00E0
     Ret,                    // 00EE
+    LoRes,                  // 00FE
+    HiRes,                  // 00FF
     Jmp(Addr),              // 1NNN
     Call(Addr),             // 2NNN
     ImEq(RegId, u8),        // 3XNN
@@ -101,6 +139,8 @@ impl Display for OpCode {
             NativeCall(addr) => write!(f, "NativeCall@x{addr:04x}"),
             DispClear => write!(f, "DispClear"),
             Ret => write!(f, "Ret"),
+            LoRes => write!(f, "LoRes"),
+            HiRes => write!(f, "HiRes"),
             Jmp(addr) => write!(f, "Jmp@x{addr:04x}"),
             Call(addr) => write!(f, "Call@x{addr:04x}"),
             ImEq(vx, vi) => write!(f, "ImEq v{vx:X},{vi:02x}"),
@@ -139,10 +179,124 @@ impl Display for OpCode {
     }
 }
 
-#[derive(PartialEq)]
-pub enum StepMode {
-    Cycle,
-    Debug,
+impl OpCode {
+    /// The mnemonic without its operands.
+    pub fn name(&self) -> &'static str {
+        use OpCode::*;
+        match self {
+            NativeCall(..) => "NativeCall",
+            DispClear => "DispClear",
+            Ret => "Ret",
+            LoRes => "LoRes",
+            HiRes => "HiRes",
+            Jmp(..) => "Jmp",
+            Call(..) => "Call",
+            ImEq(..) => "ImEq",
+            ImNeq(..) => "ImNeq",
+            RREq(..) => "RREq",
+            IRMov(..) => "IRMov",
+            IRAdd(..) => "IRAdd",
+            RRMov(..) => "RRMov",
+            RROr(..) => "RROr",
+            RRAnd(..) => "RRAnd",
+            RRXor(..) => "RRXor",
+            RRAdd(..) => "RRAdd",
+            RRSub(..) => "RRSub",
+            RRShr(..) => "RRShr",
+            RRSub2(..) => "RRSub2",
+            RRShl(..) => "RRShl",
+            RRNeq(..) => "RRNeq",
+            Index(..) => "Index",
+            JmpAdd(..) => "JmpAdd",
+            Rand(..) => "Rand",
+            Draw(..) => "Draw",
+            KeyEq(..) => "KeyEq",
+            KeyNeq(..) => "KeyNeq",
+            DelayGet(..) => "DelayGet",
+            KeyWait(..) => "KeyWait",
+            DelaySet(..) => "DelaySet",
+            SoundSet(..) => "SoundSet",
+            IncIndex(..) => "IncIndex",
+            SpriteAddr(..) => "SpriteAddr",
+            BCD(..) => "BCD",
+            RegDump(..) => "RegDump",
+            RegLoad(..) => "RegLoad",
+            Halt => "Halt",
+            Invalid => "Invalid",
+        }
+    }
+
+    /// The instruction word, `None` for `Invalid` and a `NativeCall` that
+    /// would decode as another instruction. Registers and addresses are
+    /// masked to fit their field.
+    pub fn encode(&self) -> Option<u16> {
+        use OpCode::*;
+        let x = |vx: &RegId| (*vx as u16 & 0xf) << 8;
+        let y = |vy: &RegId| (*vy as u16 & 0xf) << 4;
+        let a = |addr: &Addr| *addr as u16 & 0xfff;
+        let op = match self {
+            // decode goes by the low byte for these
+            NativeCall(addr) if matches!(addr & 0xff, 0xe0 | 0xee | 0xfe | 0xff) => return None,
+            NativeCall(addr) => a(addr),
+            DispClear => 0x00e0,
+            Ret => 0x00ee,
+            LoRes => 0x00fe,
+            HiRes => 0x00ff,
+            Jmp(addr) => 0x1000 | a(addr),
+            Call(addr) => 0x2000 | a(addr),
+            ImEq(vx, vi) => 0x3000 | x(vx) | *vi as u16,
+            ImNeq(vx, vi) => 0x4000 | x(vx) | *vi as u16,
+            RREq(vx, vy) => 0x5000 | x(vx) | y(vy),
+            IRMov(vx, vi) => 0x6000 | x(vx) | *vi as u16,
+            IRAdd(vx, vi) => 0x7000 | x(vx) | *vi as u16,
+            RRMov(vx, vy) => 0x8000 | x(vx) | y(vy),
+            RROr(vx, vy) => 0x8001 | x(vx) | y(vy),
+            RRAnd(vx, vy) => 0x8002 | x(vx) | y(vy),
+            RRXor(vx, vy) => 0x8003 | x(vx) | y(vy),
+            RRAdd(vx, vy) => 0x8004 | x(vx) | y(vy),
+            RRSub(vx, vy) => 0x8005 | x(vx) | y(vy),
+            RRShr(vx) => 0x8006 | x(vx),
+            RRSub2(vx, vy) => 0x8007 | x(vx) | y(vy),
+            RRShl(vx) => 0x800e | x(vx),
+            RRNeq(vx, vy) => 0x9000 | x(vx) | y(vy),
+            Index(addr) => 0xa000 | a(addr),
+            JmpAdd(addr) => 0xb000 | a(addr),
+            Rand(vx, vi) => 0xc000 | x(vx) | *vi as u16,
+            Draw(vx, vy, n) => 0xd000 | x(vx) | y(vy) | (*n as u16 & 0xf),
+            KeyEq(vx) => 0xe09e | x(vx),
+            KeyNeq(vx) => 0xe0a1 | x(vx),
+            DelayGet(vx) => 0xf007 | x(vx),
+            KeyWait(vx) => 0xf00a | x(vx),
+            DelaySet(vx) => 0xf015 | x(vx),
+            SoundSet(vx) => 0xf018 | x(vx),
+            IncIndex(vx) => 0xf01e | x(vx),
+            SpriteAddr(vx) => 0xf029 | x(vx),
+            BCD(vx) => 0xf033 | x(vx),
+            RegDump(vx) => 0xf055 | x(vx),
+            RegLoad(vx) => 0xf065 | x(vx),
+            Halt => 0xffff,
+            Invalid => return None,
+        };
+        Some(op)
+    }
+}
+
+/// What `Draw` does with sprite pixels that fall past the screen edge.
+/// The starting coordinate always wraps, this only affects the rest of the sprite.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeMode {
+    Clip,
+    Wrap,
+}
+
+/// When `KeyWait` is satisfied. The VIP waits for a key to be pressed and
+/// released again, CHIP-48 and later continue as soon as it is pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyWaitMode {
+    Release,
+    Press,
 }
 
 pub struct Chip8 {
@@ -150,13 +304,32 @@ pub struct Chip8 {
     mem: Vec<u8>,
     pub gfx: Vec<u8>,
     keys: u16,
+    key_pressed: u16,
+    key_released: u16,
+    key_waiting: bool,
     pub cycles: u32,
     delay_timer: u8,
     sound_timer: u8,
     pub draw: bool,
-    pub step_mode: StepMode,
-    rng: ThreadRng,
+    pub edge_mode: EdgeMode,
+    pub key_wait_mode: KeyWaitMode,
+    hires: bool,
+    rng: SmallRng,
     pub running: bool,
+    /// The program last loaded, for `reset`.
+    rom: Vec<u8>,
+    /// Addresses `I` pointed at when drawing, with the sprite height.
+    sprites: BTreeMap<usize, u8>,
+    /// Counts every instruction run when set.
+    pub profile: Option<Profile>,
+    /// Tracks the code run and data read when set.
+    pub coverage: Option<Coverage>,
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip8 {
@@ -164,15 +337,26 @@ impl Chip8 {
         let mut comp = Chip8 {
             cpu: Cpu::new(),
             mem: vec![0; MEM_SIZE],
-            gfx: vec![0; GFX_SIZE],
+            gfx: vec![0; GFX_WIDTH * GFX_HEIGHT],
             keys: 0,
+            key_pressed: 0,
+            key_released: 0,
+            key_waiting: false,
             cycles: 0,
             delay_timer: 0,
             sound_timer: 0,
             draw: true,
-            step_mode: StepMode::Cycle,
-            rng: rand::thread_rng(),
+            edge_mode: EdgeMode::Clip,
+            key_wait_mode: KeyWaitMode::Release,
+            hires: false,
+            // RandomState is seeded by the os where it can be, and doesn't
+            // need getrandom so this also works on wasm32-unknown-unknown
+            rng: SmallRng::seed_from_u64(RandomState::new().build_hasher().finish()),
             running: true,
+            rom: vec![],
+            sprites: BTreeMap::new(),
+            profile: None,
+            coverage: None,
         };
 
         comp.mem[0..80].copy_from_slice(&CHIP8_FONTSET);
@@ -180,11 +364,56 @@ impl Chip8 {
         comp
     }
 
-    pub fn with_mode(mut self, mode: StepMode) -> Self {
-        self.step_mode = mode;
+    /// Makes `Rand` reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Starts counting instructions, see [`Profile`].
+    pub fn with_profile(mut self) -> Self {
+        self.profile = Some(Profile::new());
+        self
+    }
+
+    /// Starts tracking code and data, see [`Coverage`].
+    pub fn with_coverage(mut self) -> Self {
+        self.coverage = Some(Coverage::new());
+        self
+    }
+
+    pub fn with_edge_mode(mut self, edge_mode: EdgeMode) -> Self {
+        self.edge_mode = edge_mode;
         self
     }
 
+    pub fn with_key_wait_mode(mut self, key_wait_mode: KeyWaitMode) -> Self {
+        self.key_wait_mode = key_wait_mode;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            GFX_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            GFX_HEIGHT
+        }
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.gfx = vec![0; self.width() * self.height()];
+        self.draw = true;
+    }
+
     pub fn load(&mut self, prog: &[u8]) -> Result<(), String> {
         if prog.len() >= INT_OFFSET - PROG_OFFSET {
             return Err(format!(
@@ -195,10 +424,34 @@ impl Chip8 {
         }
 
         self.mem[PROG_OFFSET..PROG_OFFSET + prog.len()].copy_from_slice(prog);
+        self.rom = prog.to_vec();
+        self.sprites.clear();
 
         Ok(())
     }
 
+    /// Goes back to the power-on state with the loaded program, keeping the
+    /// modes and the random number generator.
+    pub fn reset(&mut self) {
+        self.cpu = Cpu::new();
+        self.mem.fill(0);
+        self.mem[0..80].copy_from_slice(&CHIP8_FONTSET);
+        self.mem[PROG_OFFSET..PROG_OFFSET + self.rom.len()].copy_from_slice(&self.rom);
+        self.keys = 0;
+        self.key_pressed = 0;
+        self.key_released = 0;
+        self.key_waiting = false;
+        self.cycles = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.running = true;
+        self.sprites.clear();
+        if let Some(profile) = &mut self.profile {
+            profile.reset_calls();
+        }
+        self.set_hires(false);
+    }
+
     pub fn step(&mut self) -> Result<(), String> {
         let pc = self.cpu.pc;
         let opcode_num = match (self.mem.get(pc), self.mem.get(pc + 1)) {
@@ -207,22 +460,20 @@ impl Chip8 {
         };
 
         let opcode = Chip8::decode(opcode_num);
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc, &opcode);
+        }
         let mut skip = false;
         let mut next_pc = pc + 2;
+        // before RegLoad moves it
+        let i = self.cpu.i;
         use OpCode::*;
 
-        if self.step_mode == StepMode::Debug {
-            // println!("{:?}", self.cpu);
-            println!("pc: 0x{:04x}", pc);
-            println!("opcode: 0x{:04x} ({})", opcode_num, opcode);
-            println!("cycles: 0x{:08x}", self.cycles);
-            // println!("keys: 0x{:04x}", self.keys);
-        }
-
         match opcode {
             NativeCall(addr) => {
-                self.mem[self.cpu.sp] = (next_pc & 0xf) as u8;
-                self.mem[self.cpu.sp + 1] = ((next_pc >> 8) & 0xf) as u8;
+                let top = self.span("Stack", self.cpu.sp, 2)?;
+                self.mem[top.start] = (next_pc & 0xf) as u8;
+                self.mem[top.start + 1] = ((next_pc >> 8) & 0xf) as u8;
                 self.cpu.sp += 2;
                 next_pc = addr;
             }
@@ -233,15 +484,20 @@ impl Chip8 {
                 self.draw = true;
             }
             Ret => {
-                self.cpu.sp -= 2;
+                let sp = self.cpu.sp.checked_sub(2).ok_or("Stack underflow")?;
+                self.span("Stack", sp, 2)?;
+                self.cpu.sp = sp;
                 next_pc = self.mem[self.cpu.sp] as Addr;
                 next_pc = next_pc << 8 | self.mem[self.cpu.sp + 1] as Addr;
                 // println!("retting to 0x{:04x}", next_pc);
             }
+            LoRes => self.set_hires(false),
+            HiRes => self.set_hires(true),
             Jmp(addr) => {
                 next_pc = addr as Addr;
             }
             Call(addr) => {
+                self.span("Stack", self.cpu.sp, 2)?;
                 self.mem[self.cpu.sp] = (next_pc >> 8) as u8;
                 self.mem[self.cpu.sp + 1] = (next_pc & 0xff) as u8;
                 self.cpu.sp += 2;
@@ -312,22 +568,48 @@ impl Chip8 {
                 self.cpu.regs[reg] = rand_val & val;
             }
             Draw(vx, vy, n) => {
-                self.cpu.regs[0xf] = 0;
-                let vx = self.cpu.regs[vx] as usize;
-                let vy = self.cpu.regs[vy] as usize;
-                let n = n as usize;
-                for y in 0..n {
-                    let pixel = self.mem[self.cpu.i + y as usize];
-                    for x in 0..8 {
-                        if pixel & (0x80 >> x) != 0 {
-                            // todo: this isn't quite right 
-                            let idx = (vx + x + (y + vy) * 64) % self.gfx.len();
-                            if self.gfx[idx] == 1 {
-                                self.cpu.regs[0xf] = 1;
+                // n = 0 is SUPER-CHIP's 16x16 sprite in hires, two bytes a
+                // row, and an empty one in lores like the original
+                let size = SpriteSize::drawn(n, self.hires);
+                let i = self.span("Sprite", self.cpu.i, size.bytes())?.start;
+                if size.bytes() > 0 {
+                    self.sprites.insert(i, n);
+                }
+                let row_bytes = size.width() / 8;
+
+                let (w, h) = (self.width(), self.height());
+                let x0 = self.cpu.regs[vx] as usize % w;
+                let y0 = self.cpu.regs[vy] as usize % h;
+                self.cpu.regs[FLAG_REG] = 0;
+                for y in 0..size.height() {
+                    let mut py = y0 + y;
+                    if py >= h {
+                        match self.edge_mode {
+                            EdgeMode::Clip => break,
+                            EdgeMode::Wrap => py %= h,
+                        }
+                    }
+
+                    for x in 0..size.width() {
+                        let pixel = self.mem[i + y * row_bytes + x / 8];
+                        if pixel & (0x80 >> (x % 8)) == 0 {
+                            continue;
+                        }
+
+                        let mut px = x0 + x;
+                        if px >= w {
+                            match self.edge_mode {
+                                EdgeMode::Clip => break,
+                                EdgeMode::Wrap => px %= w,
                             }
+                        }
 
-                            self.gfx[idx] ^= 1
+                        let idx = px + py * w;
+                        if self.gfx[idx] == 1 {
+                            self.cpu.regs[FLAG_REG] = 1;
                         }
+
+                        self.gfx[idx] ^= 1
                     }
                 }
                 self.draw = true;
@@ -348,17 +630,24 @@ impl Chip8 {
                 self.sound_timer = self.cpu.regs[vx];
             }
             KeyWait(vx) => {
-                let mut got_key = false;
-                for i in 0..16 {
-                    if (self.keys & (1 << i)) > 0 {
-                        self.cpu.regs[vx] = i;
-                        got_key = true;
-                        break;
-                    }
+                // only key events that happen while blocked count, so a key
+                // still held from before can't satisfy the wait
+                if !self.key_waiting {
+                    self.key_waiting = true;
+                    self.key_pressed = 0;
+                    self.key_released = 0;
                 }
 
-                if !got_key {
+                let ready = match self.key_wait_mode {
+                    KeyWaitMode::Release => self.key_pressed & self.key_released,
+                    KeyWaitMode::Press => self.key_pressed,
+                };
+
+                if ready == 0 {
                     next_pc = pc;
+                } else {
+                    self.cpu.regs[vx] = ready.trailing_zeros() as u8;
+                    self.key_waiting = false;
                 }
             }
             IncIndex(vx) => {
@@ -373,19 +662,18 @@ impl Chip8 {
                 let h = vx / 100;
                 let t = vx / 10;
                 let o = vx % 10;
-                println!("bcd debug: {}{}{}", h, t, o);
-
-                self.mem[self.cpu.i] = h;
-                self.mem[self.cpu.i + 1] = t;
-                self.mem[self.cpu.i + 2] = o;
+                let digits = self.span("BCD", self.cpu.i, 3)?;
+                self.mem[digits].copy_from_slice(&[h, t, o]);
             }
             RegDump(vx) => {
+                self.span("RegDump", self.cpu.i, vx + 1)?;
                 for x in 0..=vx {
                     self.mem[self.cpu.i + x] = self.cpu.regs[vx];
                 }
                 self.cpu.i += vx + 1;
             }
             RegLoad(vx) => {
+                self.span("RegLoad", self.cpu.i, vx + 1)?;
                 for x in 0..=vx {
                     self.cpu.regs[vx] = self.mem[self.cpu.i + x]
                 }
@@ -400,28 +688,50 @@ impl Chip8 {
             }
         }
 
-        if skip {
-            next_pc += 2;
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, &opcode, i, skip, self.hires);
         }
 
-        if self.step_mode == StepMode::Debug {
-            println!("next_pc: {:04x}\n", next_pc);
+        if skip {
+            next_pc += 2;
         }
 
         self.cpu.pc = next_pc;
         self.cycles += 1;
 
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+        Ok(())
+    }
+
+    /// The `len` bytes of memory from `start`, or an error naming `what`
+    /// when they run past its end.
+    fn span(&self, what: &str, start: usize, len: usize) -> Result<Range<usize>, String> {
+        match start.checked_add(len) {
+            Some(end) if end <= self.mem.len() => Ok(start..end),
+            _ => Err(format!(
+                "{what} at 0x{start:04x} runs past the end of memory"
+            )),
         }
+    }
 
-        if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
+    /// Runs up to `tickrate` instructions for one 60hz frame, then ticks each
+    /// timer down once. `step` leaves the timers alone, so they run at 60hz
+    /// whatever the tickrate. With `stop_on_draw` the frame ends early once
+    /// the screen needs redrawing.
+    pub fn frame(&mut self, tickrate: u32, stop_on_draw: bool) -> Result<(), String> {
+        for _ in 0..tickrate {
+            if !self.running {
+                break;
             }
 
-            self.sound_timer -= 1;
+            let drawn = self.draw;
+            self.step()?;
+            if drawn && stop_on_draw {
+                break;
+            }
         }
 
+        self.dec_timers();
+
         Ok(())
     }
 
@@ -437,6 +747,8 @@ impl Chip8 {
             0x0 => match vi {
                 0xee => Ret,
                 0xe0 => DispClear,
+                0xfe => LoRes,
+                0xff => HiRes,
                 _ => NativeCall(addr),
             },
             0x1 => Jmp(addr),
@@ -485,12 +797,105 @@ impl Chip8 {
         }
     }
 
+    /// Registers V0 to VF.
+    pub fn regs(&self) -> &[u8] {
+        &self.cpu.regs
+    }
+
+    /// The `I` register.
+    pub fn index(&self) -> usize {
+        self.cpu.i
+    }
+
+    pub fn pc(&self) -> usize {
+        self.cpu.pc
+    }
+
+    pub fn sp(&self) -> usize {
+        self.cpu.sp
+    }
+
+    /// Return addresses on the stack, the innermost call last.
+    pub fn stack(&self) -> Vec<usize> {
+        self.mem[INT_OFFSET..self.cpu.sp.max(INT_OFFSET)]
+            .chunks(2)
+            .map(|pair| (pair[0] as usize) << 8 | *pair.get(1).unwrap_or(&0) as usize)
+            .collect()
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// The program last loaded.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// All of memory, including the font, the program and the stack.
+    pub fn mem(&self) -> &[u8] {
+        &self.mem
+    }
+
+    /// Memory for a debugger to poke at.
+    pub fn mem_mut(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
+
+    /// Sets register `n`, V0 to VF.
+    pub fn set_reg(&mut self, n: usize, val: u8) {
+        self.cpu.regs[n] = val;
+    }
+
+    pub fn set_index(&mut self, i: usize) {
+        self.cpu.i = i;
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.cpu.pc = pc;
+    }
+
+    pub fn set_sp(&mut self, sp: usize) {
+        self.cpu.sp = sp;
+    }
+
+    pub fn set_delay_timer(&mut self, val: u8) {
+        self.delay_timer = val;
+    }
+
+    pub fn set_sound_timer(&mut self, val: u8) {
+        self.sound_timer = val;
+    }
+
+    /// Every address `I` has pointed at when drawing since the program
+    /// was loaded, with the height of the last sprite drawn from it.
+    pub fn sprites(&self) -> &BTreeMap<usize, u8> {
+        &self.sprites
+    }
+
+    /// Bitmask of the keypad keys held down, bit n for key n.
+    pub fn keys(&self) -> u16 {
+        self.keys
+    }
+
     pub fn key_down(&mut self, key: usize) {
         self.keys |= 1 << key;
+        self.key_pressed |= 1 << key;
     }
 
     pub fn key_up(&mut self, key: usize) {
         self.keys &= !(1 << key);
+        // only a release after a press counts, not one of a key held from
+        // before the wait
+        self.key_released |= self.key_pressed & (1 << key);
+    }
+
+    pub fn sound_on(&self) -> bool {
+        self.sound_timer > 0
     }
 
     pub fn dec_timers(&mut self) {
@@ -503,3 +908,272 @@ impl Chip8 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A machine that has run every instruction of `prog`.
+    fn run(prog: &[u16], edge_mode: EdgeMode) -> Chip8 {
+        let bytes: Vec<u8> = prog.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut comp = Chip8::new().with_edge_mode(edge_mode);
+        comp.load(&bytes).unwrap();
+        for _ in prog {
+            comp.step().unwrap();
+        }
+        comp
+    }
+
+    fn pixel(comp: &Chip8, x: usize, y: usize) -> u8 {
+        comp.gfx[x + y * comp.width()]
+    }
+
+    // the font's 0 is f0 90 90 90 f0 at address 0, and f0 is 4 pixels wide
+
+    #[test]
+    fn timers_tick_once_per_frame() {
+        // v0 = 10, delay = v0, sound = v0, then spin
+        let mut comp = run(&[0x600a, 0xf015, 0xf018, 0x1206], EdgeMode::Clip);
+        assert_eq!(comp.delay_timer, 10);
+        assert_eq!(comp.sound_timer, 10);
+
+        comp.draw = false;
+        comp.frame(100, true).unwrap();
+        assert_eq!(comp.delay_timer, 9);
+        assert_eq!(comp.sound_timer, 9);
+
+        comp.frame(1, true).unwrap();
+        assert_eq!(comp.delay_timer, 8);
+        assert_eq!(comp.sound_timer, 8);
+    }
+
+    #[test]
+    fn frame_stops_on_draw_unless_asked_not_to() {
+        // cls, v0 = 1, then spin
+        for (stop_on_draw, cycles) in [(true, 2), (false, 10)] {
+            let mut comp = Chip8::new();
+            comp.load(&[0x00, 0xe0, 0x60, 0x01, 0x12, 0x04]).unwrap();
+            comp.draw = false;
+            comp.frame(10, stop_on_draw).unwrap();
+            assert_eq!(comp.cycles, cycles);
+        }
+    }
+
+    /// A machine blocked in `KeyWait v0` at 0x200.
+    fn waiting(mode: KeyWaitMode) -> Chip8 {
+        let mut comp = Chip8::new().with_key_wait_mode(mode);
+        comp.load(&[0xf0, 0x0a]).unwrap();
+        comp.step().unwrap();
+        assert_eq!(comp.cpu.pc, 0x200);
+        comp
+    }
+
+    #[test]
+    fn key_wait_continues_on_release() {
+        let mut comp = waiting(KeyWaitMode::Release);
+        comp.key_down(5);
+        comp.step().unwrap();
+        assert_eq!(comp.cpu.pc, 0x200);
+
+        comp.key_up(5);
+        comp.step().unwrap();
+        assert_eq!(comp.cpu.pc, 0x202);
+        assert_eq!(comp.cpu.regs[0], 5);
+    }
+
+    #[test]
+    fn key_wait_ignores_a_key_held_from_before() {
+        let mut comp = Chip8::new();
+        comp.load(&[0xf0, 0x0a]).unwrap();
+        comp.key_down(5);
+        comp.step().unwrap();
+
+        comp.key_up(5);
+        comp.step().unwrap();
+        assert_eq!(comp.cpu.pc, 0x200);
+
+        // pressed again, it still has to be released
+        comp.key_down(5);
+        comp.step().unwrap();
+        assert_eq!(comp.cpu.pc, 0x200);
+
+        comp.key_up(5);
+        comp.step().unwrap();
+        assert_eq!(comp.cpu.pc, 0x202);
+        assert_eq!(comp.cpu.regs[0], 5);
+    }
+
+    #[test]
+    fn key_wait_press_mode_continues_on_press() {
+        let mut comp = waiting(KeyWaitMode::Press);
+        comp.key_down(0xa);
+        comp.step().unwrap();
+        assert_eq!(comp.cpu.pc, 0x202);
+        assert_eq!(comp.cpu.regs[0], 0xa);
+    }
+
+    #[test]
+    fn draw_wraps_starting_coordinates() {
+        // V0 = 66, V1 = 33 land on 2, 1
+        let comp = run(&[0x6042, 0x6121, 0xa000, 0xd011], EdgeMode::Clip);
+        assert_eq!(pixel(&comp, 2, 1), 1);
+        assert_eq!(pixel(&comp, 5, 1), 1);
+        assert_eq!(pixel(&comp, 6, 1), 0);
+    }
+
+    #[test]
+    fn draw_clips_or_wraps_at_the_right_edge() {
+        let prog = [0x603e, 0x6100, 0xa000, 0xd011];
+        let clipped = run(&prog, EdgeMode::Clip);
+        assert_eq!(pixel(&clipped, 62, 0), 1);
+        assert_eq!(pixel(&clipped, 63, 0), 1);
+        assert_eq!(pixel(&clipped, 0, 0), 0);
+
+        let wrapped = run(&prog, EdgeMode::Wrap);
+        assert_eq!(pixel(&wrapped, 63, 0), 1);
+        assert_eq!(pixel(&wrapped, 0, 0), 1);
+        assert_eq!(pixel(&wrapped, 1, 0), 1);
+    }
+
+    #[test]
+    fn draw_clips_or_wraps_at_the_bottom_edge() {
+        let prog = [0x6000, 0x611e, 0xa000, 0xd015];
+        let clipped = run(&prog, EdgeMode::Clip);
+        assert_eq!(pixel(&clipped, 0, 30), 1);
+        assert_eq!(pixel(&clipped, 0, 31), 1);
+        assert_eq!(pixel(&clipped, 0, 0), 0);
+
+        let wrapped = run(&prog, EdgeMode::Wrap);
+        assert_eq!(pixel(&wrapped, 0, 0), 1);
+        assert_eq!(pixel(&wrapped, 1, 0), 0);
+        assert_eq!(pixel(&wrapped, 0, 2), 1);
+        assert_eq!(pixel(&wrapped, 1, 2), 1);
+    }
+
+    #[test]
+    fn draw_sets_vf_on_collision() {
+        let once = run(&[0xa000, 0xd015], EdgeMode::Clip);
+        assert_eq!(once.cpu.regs[FLAG_REG], 0);
+
+        let twice = run(&[0xa000, 0xd015, 0xd015], EdgeMode::Clip);
+        assert_eq!(twice.cpu.regs[FLAG_REG], 1);
+        assert!(twice.gfx.iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn hires_switches_resolution() {
+        let hires = run(&[0x00ff], EdgeMode::Clip);
+        assert_eq!((hires.width(), hires.height()), (128, 64));
+        assert_eq!(hires.gfx.len(), 128 * 64);
+
+        let lores = run(&[0x00ff, 0x00fe], EdgeMode::Clip);
+        assert_eq!((lores.width(), lores.height()), (64, 32));
+        assert_eq!(lores.gfx.len(), 64 * 32);
+    }
+
+    /// `Draw v0,v1,0` at 100, 0 of 32 bytes of 0xff after the code.
+    fn draw_0(hires: bool) -> Chip8 {
+        let mode = if hires { 0x00ff } else { 0x00fe };
+        let mut prog: Vec<u16> = vec![mode, 0xa20a, 0x6064, 0x6100, 0xd010];
+        prog.extend([0xffff; 16]);
+        let bytes: Vec<u8> = prog.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut comp = Chip8::new();
+        comp.load(&bytes).unwrap();
+        for _ in 0..5 {
+            comp.step().unwrap();
+        }
+        comp
+    }
+
+    #[test]
+    fn draw_0_is_16x16_in_hires() {
+        let comp = draw_0(true);
+        let set = (0..128).filter(|x| pixel(&comp, *x, 0) == 1).count();
+        assert_eq!(set, 16);
+        assert_eq!(pixel(&comp, 100, 15), 1);
+        assert_eq!(pixel(&comp, 115, 15), 1);
+        assert_eq!(pixel(&comp, 116, 15), 0);
+        assert_eq!(pixel(&comp, 100, 16), 0);
+        assert_eq!(comp.sprites().values().collect::<Vec<_>>(), [&0]);
+    }
+
+    #[test]
+    fn draw_0_is_empty_in_lores() {
+        let comp = draw_0(false);
+        assert!(comp.gfx.iter().all(|p| *p == 0));
+        assert_eq!(comp.cpu.regs[FLAG_REG], 0);
+        assert!(comp.sprites().is_empty());
+    }
+
+    #[test]
+    fn draw_past_the_end_of_memory_fails() {
+        let bytes: Vec<u8> = [0xafff_u16, 0xd012]
+            .iter()
+            .flat_map(|op| op.to_be_bytes())
+            .collect();
+        let mut comp = Chip8::new();
+        comp.load(&bytes).unwrap();
+        comp.step().unwrap();
+        assert!(comp.step().is_err());
+    }
+
+    #[test]
+    fn reset_keeps_the_rom_and_quirks() {
+        let prog = [
+            0x00ff, // hires
+            0x6005, // v0 = 5
+            0xf015, // delay = v0
+            0xf018, // sound = v0
+            0xa000, // i = font 0
+            0xd015, // draw it
+            0xa200, // i = 0x200
+            0xf055, // overwrite the first instruction with v0
+        ];
+        let mut comp = run(&prog, EdgeMode::Wrap);
+        comp.key_wait_mode = KeyWaitMode::Press;
+        comp.key_down(3);
+        assert!(comp.hires);
+        assert_eq!(comp.mem[0x200], 5);
+
+        comp.reset();
+        assert_eq!(comp.cpu.pc, PROG_OFFSET);
+        assert_eq!(comp.cpu.i, 0);
+        assert_eq!(comp.cpu.regs, [0; 16]);
+        assert_eq!((comp.delay_timer, comp.sound_timer), (0, 0));
+        assert_eq!((comp.keys, comp.cycles), (0, 0));
+        assert!(!comp.hires);
+        assert_eq!(comp.gfx.len(), 64 * 32);
+        assert!(comp.gfx.iter().all(|p| *p == 0));
+
+        assert_eq!(comp.mem[0x200..0x202], [0x00, 0xff]);
+        assert_eq!(comp.mem[0..5], CHIP8_FONTSET[0..5]);
+        assert_eq!(comp.edge_mode, EdgeMode::Wrap);
+        assert_eq!(comp.key_wait_mode, KeyWaitMode::Press);
+        assert!(comp.running);
+    }
+
+    #[test]
+    fn encode_round_trips_decode() {
+        for word in 0..=0xffff {
+            let op = Chip8::decode(word);
+            if op == OpCode::Invalid {
+                continue;
+            }
+            let encoded = op.encode().unwrap_or_else(|| panic!("{word:04x} {op}"));
+            assert_eq!(Chip8::decode(encoded), op, "{word:04x}");
+        }
+    }
+
+    #[test]
+    fn native_call_colliding_with_another_instruction_is_rejected() {
+        for addr in [0x0e0, 0x0ee, 0x0fe, 0x0ff, 0x1e0] {
+            assert_eq!(OpCode::NativeCall(addr).encode(), None);
+        }
+        assert_eq!(OpCode::NativeCall(0x123).encode(), Some(0x0123));
+
+        let Err(err) = asm::assemble("NativeCall @x0ee") else {
+            panic!("assembled a NativeCall that runs as Ret");
+        };
+        assert!(err.contains("would run as Ret"), "{err}");
+    }
+}
@@ -1,261 +1,932 @@
-use std::time::Duration;
-use std::u32;
-
-use chip8::{Chip8, StepMode};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::rect::Rect;
-use sdl2::render::{Texture, TextureCreator};
-use sdl2::video::WindowContext;
-
-struct Opts {
-    mode: StepMode,
-    file: String,
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+
+use clap::{value_parser, Args, Parser, Subcommand};
+
+use chip8::asm::{self, SourceMap};
+use chip8::config::Config;
+use chip8::coverage::Coverage;
+use chip8::crt::CrtEffects;
+use chip8::dap;
+use chip8::dump::{VideoFormat, VideoWriter, WavWriter};
+use chip8::filter::FilterMode;
+use chip8::frontend::{AudioSink, Exit};
+use chip8::gdb;
+use chip8::image::{self, RecordFormat, Recording};
+use chip8::keymap::KeyMap;
+use chip8::movie::Movie;
+use chip8::palette::parse_color;
+use chip8::palette::Palette;
+use chip8::profile::Profile;
+use chip8::romdb::{self, RomDb, RomEntry};
+use chip8::sprites::{self, Sheet, SpriteSize};
+use chip8::{Chip8, EdgeMode, KeyWaitMode, OpCode, CHIP8_FONTSET, PROG_OFFSET};
+
+/// CHIP-8 interpreter and tools. Without a subcommand the ROM is run.
+#[derive(Parser)]
+#[command(
+    name = "chip8",
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a ROM in a window, or the terminal with --tui
+    Run(RunArgs),
+    /// Run a ROM paused, with registers, code and memory next to the game
+    Debug(RunArgs),
+    /// Run a ROM as fast as possible without a display
+    Headless(HeadlessArgs),
+    /// Disassemble a ROM
+    Dasm(DasmArgs),
+    /// Assemble the disassembler's syntax into a ROM
+    Asm(AsmArgs),
+    /// Show a ROM's hash, database entry and what it uses
+    Info(InfoArgs),
+    /// Add the community CHIP-8 database's programs.json to the ROM database
+    ImportDb(ImportArgs),
+    /// Show memory as sprites, or the ones a ROM draws with --trace
+    Sprites(SpritesArgs),
+    /// Serve a ROM to gdb or another remote protocol debugger
+    Gdb(GdbArgs),
+    /// Speak the Debug Adapter Protocol on stdin and stdout, for editors
+    Dap,
+}
+
+#[derive(Args)]
+struct RomArg {
+    /// ROM file
+    #[arg(required_unless_present = "file")]
+    rom: Option<PathBuf>,
+
+    /// ROM file, for compatibility with older versions
+    #[arg(short, long, conflicts_with = "rom", hide = true)]
+    file: Option<PathBuf>,
+}
+
+impl RomArg {
+    fn path(&self) -> Result<&Path, String> {
+        self.rom
+            .as_deref()
+            .or(self.file.as_deref())
+            .ok_or_else(|| "Missing ROM, see --help".into())
+    }
+
+    fn read(&self) -> Result<(&Path, Vec<u8>), String> {
+        let path = self.path()?;
+        let prog = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok((path, prog))
+    }
+}
+
+#[derive(Args)]
+struct EmuArgs {
+    #[command(flatten)]
+    rom: RomArg,
+
+    /// Instructions per frame [default: the ROM database's, or 20]
+    #[arg(short, long, visible_alias = "time")]
+    tickrate: Option<u32>,
+
+    /// Wrap sprites around the screen edges instead of clipping them
+    #[arg(short, long)]
+    wrap: bool,
+
+    /// Continue from KeyWait when a key is pressed rather than released
+    #[arg(long)]
+    wait_press: bool,
+
+    /// Seed for the random number generator
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Config file [default: ~/.config/chip8/config.toml]
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// ROM database [default: ~/.config/chip8/roms.toml]
+    #[arg(long)]
+    romdb: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct DisplayArgs {
+    /// Palette name, see the config for custom ones
+    #[arg(short, long)]
+    palette: Option<String>,
+
+    /// Foreground color, overriding the palette's
+    #[arg(long, value_parser = parse_color)]
+    fg: Option<u32>,
+
+    /// Background color, overriding the palette's
+    #[arg(long, value_parser = parse_color)]
+    bg: Option<u32>,
+
+    /// Initial window size as a multiple of 64x32
+    #[arg(short, long, default_value_t = 16, value_parser = value_parser!(u32).range(1..=64))]
+    scale: u32,
+
+    /// How the screen fills the window: integer, fit or stretch
+    #[cfg(feature = "sdl")]
+    #[arg(long, default_value = "integer")]
+    scaling: chip8::frontend::Scaling,
+
+    /// Flicker filter: none, phosphor, blend or vblank
+    #[arg(long, default_value = "none")]
+    filter: FilterMode,
+
+    /// Frames the phosphor filter takes to fade out
+    #[arg(long, default_value_t = 8)]
+    decay: u32,
+
+    /// CRT effects: all, or some of scanlines,grid,bloom,curvature
+    #[arg(long)]
+    crt: Option<CrtEffects>,
+
+    /// Show frame rate and speed over the game
+    #[arg(long)]
+    hud: bool,
+
+    /// Run in the terminal instead of a window
+    #[arg(long)]
+    tui: bool,
+
+    /// Where screenshots and recordings go
+    #[arg(long, default_value = ".")]
+    capture_dir: PathBuf,
+
+    /// Recording format: gif or apng
+    #[arg(long, default_value = "gif")]
+    record_format: RecordFormat,
+
+    /// Start recording straight away, it's saved on exit
+    #[arg(long)]
+    record: bool,
+
+    /// Save the input to a movie file on exit, to play back with headless
+    #[arg(long)]
+    save_movie: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    emu: EmuArgs,
+
+    #[command(flatten)]
+    display: DisplayArgs,
+}
+
+#[derive(Args)]
+struct HeadlessArgs {
+    #[command(flatten)]
+    emu: EmuArgs,
+
+    /// Frames to run, at 60 a second [default: the movie's length, or 600]
+    #[arg(long)]
+    frames: Option<u32>,
+
+    /// Play back a movie saved with run --save-movie, in place of the seed,
+    /// quirks and tickrate
+    #[arg(long)]
+    movie: Option<PathBuf>,
+
+    /// Write every frame to a file, or - for stdout
+    #[arg(long)]
+    video: Option<PathBuf>,
+
+    /// Video format: raw RGB24 or y4m [default: y4m for .y4m files and stdout, raw otherwise]
+    #[arg(long)]
+    video_format: Option<VideoFormat>,
+
+    /// Write the beeper to a WAV file
+    #[arg(long)]
+    audio: Option<PathBuf>,
+
+    /// Print the screen when done
+    #[arg(long)]
+    screen: bool,
+
+    /// Save the screen as a PNG when done
+    #[arg(long)]
+    screenshot: Option<PathBuf>,
+
+    /// Record every frame to a .gif or .png file
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Size of a screen pixel in screenshots, recordings and video
+    #[arg(short, long, default_value_t = 8)]
+    scale: usize,
+
+    /// Palette for screenshots, recordings and video
+    #[arg(short, long)]
+    palette: Option<String>,
+
+    /// Print where the instructions went when done
+    #[arg(long)]
+    profile: bool,
+
+    /// Write the profile's call stacks in folded format, for flame graphs
+    #[arg(long)]
+    folded: Option<PathBuf>,
+
+    /// Addresses to list in the profile
+    #[arg(long, default_value_t = 20)]
+    top: usize,
+
+    /// Write the disassembly with what ran and what was read as data
+    #[arg(long)]
+    coverage: Option<PathBuf>,
+
+    /// Write an LCOV tracefile for --source, or the --coverage disassembly
+    #[arg(long)]
+    lcov: Option<PathBuf>,
+
+    /// Source the ROM was assembled from, for --lcov
+    #[arg(long)]
+    source: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct DasmArgs {
+    #[command(flatten)]
+    rom: RomArg,
+
+    /// Write the listing here instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct AsmArgs {
+    /// Source file
+    source: PathBuf,
+
+    /// ROM to write [default: the source with a .ch8 extension]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Also write a source map for debuggers, see the dap command
+    #[arg(long)]
+    map: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    #[command(flatten)]
+    rom: RomArg,
+
+    /// ROM database [default: ~/.config/chip8/roms.toml]
+    #[arg(long)]
+    romdb: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ImportArgs {
+    /// programs.json from the community database
+    programs: PathBuf,
+
+    /// ROM database to add to [default: ~/.config/chip8/roms.toml]
+    #[arg(long)]
+    romdb: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct SpritesArgs {
+    #[command(flatten)]
+    emu: EmuArgs,
+
+    /// First address, in hex [default: 200, where programs start]
+    #[arg(long, value_parser = parse_hex)]
+    start: Option<usize>,
+
+    /// Bytes to show, in hex [default: to the end of the ROM]
+    #[arg(long, value_parser = parse_hex)]
+    len: Option<usize>,
+
+    /// Sprite size: 1-15 rows, or 16x16 for SUPER-CHIP [default: 8, or 5 with --font]
+    #[arg(long)]
+    size: Option<SpriteSize>,
+
+    /// Show the built-in font at address 0
+    #[arg(long, conflicts_with_all = ["start", "len", "trace"])]
+    font: bool,
+
+    /// Run this many frames and show every sprite drawn
+    #[arg(long, conflicts_with_all = ["start", "len", "size"])]
+    trace: Option<u32>,
+
+    /// Sprites per row
+    #[arg(long, default_value_t = 8)]
+    columns: usize,
+
+    /// Save the sheet as a PNG instead of printing it
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Size of a pixel in the PNG
+    #[arg(short, long, default_value_t = 8)]
+    scale: usize,
+
+    /// Palette for the PNG
+    #[arg(short, long)]
+    palette: Option<String>,
+}
+
+#[derive(Args)]
+struct GdbArgs {
+    #[command(flatten)]
+    emu: EmuArgs,
+
+    /// Port to listen on, on localhost only
+    #[arg(long, default_value_t = 1234)]
+    port: u16,
+}
+
+fn parse_hex(s: &str) -> Result<usize, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    usize::from_str_radix(digits, 16).map_err(|_| format!("bad hex number {s}"))
+}
+
+/// A loaded ROM with everything the config and ROM database say about it.
+#[cfg_attr(not(any(feature = "sdl", feature = "tui")), allow(dead_code))]
+struct Session {
+    comp: Chip8,
+    /// The ROM's file name without the extension.
+    name: String,
+    seed: u64,
     tickrate: u32,
-    fg: u32,
-    bg: u32,
-}
-
-// TODO this sucks
-fn parse_cl() -> Result<Opts, String> {
-    let args: Vec<String> = std::env::args().collect();
-    let mode = match args.iter().position(|e| e == "-m" || e == "--mode") {
-        Some(idx) => match args.get(idx + 1) {
-            Some(val) => match val.as_str() {
-                "d" | "debug" => StepMode::Debug,
-                "c" | "cycle" | _ => StepMode::Cycle,
-            },
-            None => StepMode::Cycle,
-        },
-        None => StepMode::Cycle,
-    };
+    config: Config,
+    entry: RomEntry,
+    keymap: KeyMap,
+}
 
-    let file = match args.iter().position(|e| e == "-f" || e == "--file") {
-        Some(idx) => match args.get(idx + 1) {
-            Some(val) => val,
-            None => return Err("Found --file option, but no file name".into()),
-        },
-        None => return Err("Missing required option filename".into()),
-    };
+fn load(args: &EmuArgs) -> Result<Session, String> {
+    let (path, prog) = args.rom.read()?;
+    let entry = RomDb::load(args.romdb.as_deref())?
+        .lookup(&prog)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(name) = &entry.name {
+        eprintln!("Found {name} in rom db");
+    }
 
-    let tickrate = match args.iter().position(|e| e == "-t" || e == "--time") {
-        Some(idx) => match args.get(idx + 1) {
-            Some(val) => val.parse::<u32>().map_err(|e| e.to_string())?,
-            None => return Err("Found --time option, but no time value".into()),
-        },
-        None => 20,
+    let edge_mode = match args.wrap {
+        true => EdgeMode::Wrap,
+        false => entry.edge_mode().unwrap_or(EdgeMode::Clip),
     };
-
-    let fg = match args.iter().position(|e| e == "--fg") {
-        Some(idx) => match args.get(idx + 1) {
-            Some(val) => u32::from_str_radix(val, 16).unwrap(),
-            None => return Err("Found --fg option, but no color val".into()),
-        },
-        None => 0xffffff,
+    let key_wait_mode = match args.wait_press {
+        true => KeyWaitMode::Press,
+        false => entry.key_wait_mode().unwrap_or(KeyWaitMode::Release),
     };
 
-    let bg = match args.iter().position(|e| e == "--bg") {
-        Some(idx) => match args.get(idx + 1) {
-            Some(val) => u32::from_str_radix(val, 16).unwrap(),
-            None => return Err("Found --bg option, but no color value".into()),
-        },
-        None => 0x0,
-    };
+    // always picked here so a movie can save it
+    let seed = args
+        .seed
+        .unwrap_or_else(|| RandomState::new().build_hasher().finish());
+    let mut comp = Chip8::new()
+        .with_seed(seed)
+        .with_edge_mode(edge_mode)
+        .with_key_wait_mode(key_wait_mode);
+    comp.load(&prog)?;
 
-    let file = file.to_owned();
-    Ok(Opts {
-        mode,
-        file,
-        tickrate,
-        fg,
-        bg,
+    let config = Config::load(args.config.as_deref())?;
+    let rom_name = path.file_stem().and_then(|s| s.to_str());
+    let mut keymap = config.keymap(rom_name)?;
+    keymap.extend(&entry.keys)?;
+
+    Ok(Session {
+        comp,
+        name: rom_name.unwrap_or("chip8").to_owned(),
+        seed,
+        tickrate: args.tickrate.or(entry.tickrate).unwrap_or(20),
+        config,
+        entry,
+        keymap,
     })
 }
 
-fn main() -> Result<(), String> {
-    // init gfx and key read contexts
+fn run(args: &RunArgs, debug: bool) -> Result<(), String> {
+    let tui = args.display.tui || !cfg!(feature = "sdl");
+    if debug && tui {
+        return Err("The debugger needs the SDL window".into());
+    }
 
-    let opts = parse_cl()?;
+    let session = load(&args.emu)?;
+    if tui {
+        run_tui(&args.display, session)
+    } else {
+        run_sdl(&args.display, session, debug)
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn run_sdl(args: &DisplayArgs, session: Session, debug: bool) -> Result<(), String> {
+    use chip8::frontend::Frontend;
+    use chip8::sdl::{SdlAudio, SdlDisplay, SdlInput};
 
-    let win_width = 1024;
-    let win_height = 512;
     let sdl_context = sdl2::init()?;
     let video_context = sdl_context.video()?;
     let window = video_context
-        .window("chip8", win_width, win_height)
+        .window("chip8", 64 * args.scale, 32 * args.scale)
         .position_centered()
+        .resizable()
         .opengl()
         .build()
         .map_err(|e| e.to_string())?;
 
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
     let creator = canvas.texture_creator();
-    let tex_w = win_width / 64;
-    let tex_h = win_height / 32;
-    let bg = create_colored_rect(&creator, opts.bg, tex_h, tex_w)?;
-    let fg = create_colored_rect(&creator, opts.fg, tex_h, tex_w)?;
-    let mut render_rect = Rect::new(1, 1, tex_w as u32, tex_h as u32);
-
-    let mut comp = Chip8::new().with_mode(opts.mode);
-    let prog = std::fs::read(opts.file).map_err(|e| e.to_string())?;
-    comp.load(&prog)?;
+    let display = SdlDisplay::new(canvas, &creator)?
+        .with_scaling(args.scaling)
+        .with_crt(args.crt.unwrap_or_default())
+        .with_hud(args.hud)
+        .with_debugger(debug);
+    let audio = SdlAudio::new(&sdl_context);
+    let input = SdlInput::new(&sdl_context)?;
+
+    let mut frontend = Frontend::new(display, audio, input);
+    // start on the first instruction
+    frontend.paused = debug;
+    drive(frontend, args, session)
+}
 
-    let mut event_pump = sdl_context.event_pump()?;
-    let mut user_break = false;
-    'render: while comp.running {
-        let mut i = 0;
-        while i < opts.tickrate {
-            if comp.draw {
-                i = opts.tickrate;
-            }
-            match comp.step() {
-                Ok(_) => {}
-                Err(e) => {
-                    return Err(format!("emu step fail: {} on cc {}", e, comp.cycles));
-                }
-            }
+#[cfg(not(feature = "sdl"))]
+fn run_sdl(_: &DisplayArgs, _: Session, _: bool) -> Result<(), String> {
+    Err("Built without the sdl feature".into())
+}
 
-            i += 1;
-        }
+#[cfg(feature = "tui")]
+fn run_tui(args: &DisplayArgs, session: Session) -> Result<(), String> {
+    use chip8::frontend::{Frontend, NoAudio};
+    use chip8::tui::{TermDisplay, TermInput};
 
-        comp.dec_timers();
+    let display = TermDisplay::new()?;
+    let input = TermInput::new(&display);
+    drive(Frontend::new(display, NoAudio, input), args, session)
+}
 
-        if comp.draw {
-            for i in 0..comp.gfx.len() {
-                let y = (i / 64) * tex_h as usize;
-                let x = (i % 64) * tex_w as usize;
+#[cfg(not(feature = "tui"))]
+fn run_tui(_: &DisplayArgs, _: Session) -> Result<(), String> {
+    Err("Built without the tui feature".into())
+}
 
-                render_rect.set_x(x as i32);
-                render_rect.set_y(y as i32);
+/// Sets up `frontend` from the args and session and runs it to the end.
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn drive<D, A, I>(
+    frontend: chip8::frontend::Frontend<D, A, I>,
+    args: &DisplayArgs,
+    session: Session,
+) -> Result<(), String>
+where
+    D: chip8::frontend::DisplaySink,
+    A: chip8::frontend::AudioSink,
+    I: chip8::frontend::InputSource,
+{
+    use chip8::filter::FrameFilter;
+    use chip8::image::Capture;
+
+    let Session {
+        mut comp,
+        name,
+        seed,
+        tickrate,
+        config,
+        entry,
+        keymap,
+    } = session;
+
+    let (mut palette, palettes) = pick_palette(args.palette.as_ref(), &config, &entry)?;
+    if let Some(bg) = args.bg {
+        palette.colors[0] = bg;
+    }
+    if let Some(fg) = args.fg {
+        palette.colors[1] = fg;
+    }
 
-                if comp.gfx[i] == 1 {
-                    canvas.copy(&fg, None, Some(render_rect))?;
-                } else {
-                    canvas.copy(&bg, None, Some(render_rect))?
-                }
-            }
-            comp.draw = false;
-            canvas.present();
-        }
+    let mut frontend = frontend
+        .with_tickrate(tickrate)
+        .with_palette(palette)
+        .with_palettes(palettes)
+        .with_keymap(keymap)
+        .with_filter(FrameFilter::new(args.filter).with_decay(args.decay))
+        .with_capture(Capture {
+            dir: args.capture_dir.clone(),
+            name,
+            scale: args.scale as usize,
+            format: args.record_format,
+        })
+        .with_recording(args.record);
+    if args.save_movie.is_some() {
+        frontend = frontend.with_movie(Movie::new(&comp, seed));
+    }
+    let exit = frontend.run(&mut comp);
+    let saved = frontend.stop_recording();
+    let movie = frontend.movie.take();
+    let changed = frontend.keymap_changed.then(|| frontend.keymap.to_toml());
+    let error = frontend.status.error.take();
+    // the terminal frontend has to give the screen back before printing
+    drop(frontend);
+
+    println!("{}", exit_message(exit?, &comp));
+    if let Some(saved) = saved {
+        println!("{saved}");
+    }
+    if let (Some(path), Some(movie)) = (&args.save_movie, movie) {
+        movie.save(path)?;
+        println!(
+            "Saved {} frames of input to {}",
+            movie.length,
+            path.display()
+        );
+    }
+    if let Some(keys) = changed {
+        println!("\nKey bindings changed, to keep them add this to your config:\n");
+        print!("{keys}");
+    }
 
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    user_break = true;
-                    break 'render;
-                }
-                Event::KeyDown {
-                    keycode: Some(key),
-                    repeat: false,
-                    ..
-                } => {
-                    handle_key(&mut comp, key, false);
-                }
-                Event::KeyUp {
-                    keycode: Some(key), ..
-                } => {
-                    handle_key(&mut comp, key, true);
-                }
-                _ => {}
-            }
-        }
+    match error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
 
-        // naively target 60 fps
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+/// The palette from `-p`, else the ROM database entry, else the config, and
+/// every palette to cycle through.
+fn pick_palette(
+    name: Option<&String>,
+    config: &Config,
+    entry: &RomEntry,
+) -> Result<(Palette, Vec<Palette>), String> {
+    let palettes = config.palettes()?;
+    let mut palette = match name.or(entry.palette.as_ref()).or(config.palette.as_ref()) {
+        Some(name) => palettes
+            .iter()
+            .find(|p| p.name == *name)
+            .cloned()
+            .ok_or_else(|| {
+                let names: Vec<&str> = palettes.iter().map(|p| p.name.as_str()).collect();
+                format!(
+                    "Unknown palette {name}, expected one of {}",
+                    names.join(", ")
+                )
+            })?,
+        None => Palette::default(),
+    };
+    if name.is_none() && !entry.colors.is_empty() {
+        palette = Palette::parse(entry.name.as_deref().unwrap_or("rom"), &entry.colors)?;
     }
 
-    println!(
+    Ok((palette, palettes))
+}
+
+fn exit_message(exit: Exit, comp: &Chip8) -> String {
+    format!(
         "{} {} cycles",
-        if user_break {
+        if exit == Exit::Quit {
             "Stopped after"
         } else {
             "Completed in"
         },
         comp.cycles
-    );
+    )
+}
+
+fn headless(args: &HeadlessArgs) -> Result<(), String> {
+    let Session {
+        mut comp,
+        tickrate,
+        config,
+        entry,
+        ..
+    } = load(&args.emu)?;
+
+    let movie = args.movie.as_deref().map(Movie::load).transpose()?;
+    let mut player = match &movie {
+        Some(movie) => {
+            comp = movie.machine(comp.rom())?;
+            Some(movie.player())
+        }
+        None => None,
+    };
+    if args.profile || args.folded.is_some() {
+        comp.profile = Some(Profile::new());
+    }
+    if args.coverage.is_some() || args.lcov.is_some() {
+        if args.lcov.is_some() && args.source.is_none() && args.coverage.is_none() {
+            return Err("--lcov needs --source or --coverage for the line numbers".into());
+        }
+        comp.coverage = Some(Coverage::new());
+    }
+    let frames = args
+        .frames
+        .or(movie.as_ref().map(|m| m.length))
+        .unwrap_or(600);
+
+    let palette = match args.screenshot.is_some() || args.record.is_some() || args.video.is_some() {
+        true => Some(pick_palette(args.palette.as_ref(), &config, &entry)?.0),
+        false => None,
+    };
+    let mut recording = args.record.as_ref().map(|_| Recording::new());
+    let mut video = match (&args.video, &palette) {
+        (Some(path), Some(palette)) => {
+            let stdout = path == Path::new("-");
+            let format = args.video_format.unwrap_or(match stdout {
+                true => VideoFormat::Y4m,
+                false => VideoFormat::from_path(path),
+            });
+            let video = VideoWriter::new(VideoWriter::open(path)?, format, palette, args.scale);
+            eprintln!(
+                "Writing {}x{} {} video at 60 fps",
+                video.width, video.height, format
+            );
+            Some(video)
+        }
+        _ => None,
+    };
+    let mut audio = args.audio.as_deref().map(WavWriter::create).transpose()?;
+
+    for _ in 0..frames {
+        if !comp.running {
+            break;
+        }
+        match &mut player {
+            Some(player) => player.frame(&mut comp),
+            None => comp.frame(tickrate, false),
+        }
+        .map_err(|e| format!("emu step fail: {} on cc {}", e, comp.cycles))?;
+
+        if let Some(recording) = &mut recording {
+            recording.push(&comp);
+        }
+        if let Some(video) = &mut video {
+            video.write(&comp)?;
+        }
+        if let Some(audio) = &mut audio {
+            audio.set_tone(comp.sound_on());
+        }
+    }
+
+    if let Some(video) = &mut video {
+        video.finish()?;
+    }
+    if let Some(audio) = &mut audio {
+        audio.finish()?;
+    }
+    if let Some(palette) = &palette {
+        if let Some(path) = &args.screenshot {
+            image::screenshot(path, &comp, palette, args.scale)?;
+        }
+        if let (Some(path), Some(recording)) = (&args.record, &recording) {
+            recording.save(path, palette, args.scale)?;
+        }
+    }
+
+    // keep stdout for the video
+    let video_stdout = args.video.as_deref() == Some(Path::new("-"));
+    let report = |text: &str| match video_stdout {
+        true => eprint!("{text}"),
+        false => print!("{text}"),
+    };
+
+    if let Some(profile) = &comp.profile {
+        if let Some(path) = &args.folded {
+            std::fs::write(path, profile.folded())
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+        }
+        if args.profile {
+            report(&profile.report(comp.mem(), args.top));
+        }
+    }
+
+    if let Some(coverage) = &comp.coverage {
+        write_coverage(args, coverage, comp.rom())?;
+    }
+
+    if args.screen {
+        for row in comp.gfx.chunks(comp.width()) {
+            let line: String = row
+                .iter()
+                .map(|p| if *p != 0 { '#' } else { '.' })
+                .collect();
+            report(&format!("{line}\n"));
+        }
+    }
 
+    let exit = if comp.running {
+        Exit::Quit
+    } else {
+        Exit::Halted
+    };
+    report(&format!("{}\n", exit_message(exit, &comp)));
     Ok(())
 }
 
-fn _debug_render(gfx: &[u8]) {
-    for y in 0..32 {
-        for x in 0..64 {
-            if gfx[x + y * 64] > 0 {
-                print!("⬜");
-            } else {
-                print!("⬛");
+fn write_coverage(args: &HeadlessArgs, coverage: &Coverage, rom: &[u8]) -> Result<(), String> {
+    let write = |path: &Path, text: String| {
+        std::fs::write(path, text).map_err(|e| format!("{}: {e}", path.display()))
+    };
+    let listing = coverage.annotate(rom);
+    if let Some(path) = &args.coverage {
+        write(path, listing.clone())?;
+    }
+
+    if let Some(path) = &args.lcov {
+        let (source, text) = match (&args.source, &args.coverage) {
+            (Some(source), _) => {
+                let text = std::fs::read_to_string(source)
+                    .map_err(|e| format!("{}: {e}", source.display()))?;
+                (source, text)
             }
+            (None, Some(listing_path)) => (listing_path, listing),
+            (None, None) => unreachable!("checked before the run"),
+        };
+        let prog = asm::assemble(&text).map_err(|e| format!("{}: {e}", source.display()))?;
+        if prog.bytes != rom {
+            return Err(format!("{} doesn't assemble to the rom", source.display()));
         }
-        println!();
+        write(path, coverage.lcov(&source.display().to_string(), &prog))?;
     }
-    println!();
-}
-
-// TODO still don't like this but better
-fn handle_key(comp: &mut Chip8, key: Keycode, keyup: bool) {
-    let key = match key {
-        Keycode::Num1 => 0x1,
-        Keycode::Num2 => 0x2,
-        Keycode::Num3 => 0x3,
-        Keycode::Num4 => 0xc,
-
-        Keycode::Q => 0x4,
-        Keycode::W => 0x5,
-        Keycode::E => 0x6,
-        Keycode::R => 0xd,
-
-        Keycode::A => 0x7,
-        Keycode::S => 0x8,
-        Keycode::D => 0x9,
-        Keycode::F => 0xe,
-
-        Keycode::Z => 0xa,
-        Keycode::X => 0x0,
-        Keycode::C => 0xb,
-        Keycode::V => 0xf,
-        _ => 16,
-    };
+    Ok(())
+}
 
-    if key > 15 {
-        return;
+fn dasm(args: &DasmArgs) -> Result<(), String> {
+    let (_, prog) = args.rom.read()?;
+    let listing = asm::disassemble(&prog);
+    match &args.output {
+        Some(out) => std::fs::write(out, listing).map_err(|e| e.to_string()),
+        None => {
+            print!("{listing}");
+            Ok(())
+        }
     }
+}
 
-    if keyup {
-        comp.key_up(key)
-    } else {
-        comp.key_down(key)
+fn assemble(args: &AsmArgs) -> Result<(), String> {
+    let src = std::fs::read_to_string(&args.source)
+        .map_err(|e| format!("{}: {e}", args.source.display()))?;
+    let program = asm::assemble(&src).map_err(|e| format!("{}: {e}", args.source.display()))?;
+    let out = match &args.output {
+        Some(out) => out.clone(),
+        None => args.source.with_extension("ch8"),
+    };
+    std::fs::write(&out, &program.bytes).map_err(|e| format!("{}: {e}", out.display()))?;
+    println!("Wrote {} bytes to {}", program.bytes.len(), out.display());
+
+    if let Some(path) = &args.map {
+        // the source relative to the map, the way it's read back
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let source = match (args.source.canonicalize(), dir.canonicalize()) {
+            (Ok(source), Ok(dir)) => source
+                .strip_prefix(&dir)
+                .map(Path::to_path_buf)
+                .unwrap_or(source),
+            _ => args.source.clone(),
+        };
+        let map = SourceMap {
+            source,
+            lines: program.lines,
+        };
+        map.save(path)?;
+        println!("Wrote the source map to {}", path.display());
     }
+    Ok(())
 }
 
-fn create_colored_rect(
-    creator: &TextureCreator<WindowContext>,
-    c: u32,
-    tex_h: u32,
-    tex_w: u32,
-) -> Result<Texture, String> {
-    let mut tex = creator
-        .create_texture_streaming(PixelFormatEnum::RGB24, tex_w, tex_h)
-        .map_err(|e| e.to_string())?;
+fn info(args: &InfoArgs) -> Result<(), String> {
+    let (path, prog) = args.rom.read()?;
+    println!("file:    {}", path.display());
+    println!("size:    {} bytes", prog.len());
+    println!("sha1:    {}", romdb::sha1_hex(&prog));
+
+    let ops: Vec<OpCode> = prog
+        .chunks(2)
+        .map(|w| Chip8::decode(((w[0] as u16) << 8) | w.get(1).copied().unwrap_or(0) as u16))
+        .collect();
+    let hires = ops
+        .iter()
+        .any(|op| matches!(op, OpCode::HiRes | OpCode::LoRes));
+    let invalid = ops
+        .iter()
+        .filter(|op| matches!(op, OpCode::Invalid))
+        .count();
+    println!("hires:   {}", if hires { "yes" } else { "no" });
+    println!("invalid: {invalid} words, data or unsupported instructions");
+
+    match RomDb::load(args.romdb.as_deref())?.lookup(&prog) {
+        Some(entry) => {
+            let toml = toml::to_string(entry).map_err(|e| e.to_string())?;
+            println!("\nrom db entry:\n{toml}");
+        }
+        None => println!("\nnot in the rom db"),
+    }
+
+    Ok(())
+}
 
-    let b = (c & 0xff) as u8;
-    let g = (c >> 8 & 0xff) as u8;
-    let r = (c >> 16 & 0xff) as u8;
-
-    tex.with_lock(None, |buf: &mut [u8], pitch: usize| {
-        for y in 0..tex_h {
-            for x in 0..tex_w {
-                let offset = y as usize * pitch + x as usize * 3;
-                buf[offset] = r;
-                buf[offset + 1] = g;
-                buf[offset + 2] = b;
+fn import_db(args: &ImportArgs) -> Result<(), String> {
+    let path = match &args.romdb {
+        Some(path) => path.clone(),
+        None => romdb::default_path().ok_or("No rom db path, use --romdb")?,
+    };
+    // importing creates the database
+    let mut db = match path.exists() {
+        true => RomDb::load(Some(&path))?,
+        false => RomDb::default(),
+    };
+    let json = std::fs::read_to_string(&args.programs)
+        .map_err(|e| format!("{}: {e}", args.programs.display()))?;
+    let added = db.import_community(&json)?;
+    db.save(&path)?;
+    println!("Imported {added} roms into {}", path.display());
+    Ok(())
+}
+
+fn sprites(args: &SpritesArgs) -> Result<(), String> {
+    let Session {
+        mut comp,
+        tickrate,
+        config,
+        entry,
+        ..
+    } = load(&args.emu)?;
+
+    let list: Vec<(usize, SpriteSize)> = if let Some(frames) = args.trace {
+        for _ in 0..frames {
+            if !comp.running {
+                break;
             }
+            comp.frame(tickrate, false)
+                .map_err(|e| format!("emu step fail: {} on cc {}", e, comp.cycles))?;
         }
-    })?;
+        comp.sprites()
+            .iter()
+            .map(|(addr, n)| (*addr, SpriteSize::from_height(*n)))
+            .collect()
+    } else if args.font {
+        let size = args.size.unwrap_or(SpriteSize::Rows(5));
+        sprites::region(0, CHIP8_FONTSET.len(), size)
+    } else {
+        let (_, prog) = args.emu.rom.read()?;
+        let start = args.start.unwrap_or(PROG_OFFSET);
+        let end = comp.mem().len();
+        let len = args
+            .len
+            .unwrap_or((PROG_OFFSET + prog.len()).saturating_sub(start))
+            .min(end.saturating_sub(start));
+        sprites::region(start, len, args.size.unwrap_or(SpriteSize::Rows(8)))
+    };
+    if list.is_empty() {
+        return Err("No sprites to show".into());
+    }
+
+    let sheet = Sheet::new(comp.mem(), &list, args.columns);
+    match &args.output {
+        Some(path) => {
+            let (palette, _) = pick_palette(args.palette.as_ref(), &config, &entry)?;
+            let [bg, fg, _, gap] = palette.rgb();
+            let scale = args.scale.max(1);
+            let rgb = sheet.rgb([bg, fg, gap], scale);
+            image::save_png(path, &rgb, sheet.width * scale, sheet.height * scale)?;
+            println!("Saved {} sprites to {}", list.len(), path.display());
+        }
+        None => print!("{}", sheet.text()),
+    }
+    Ok(())
+}
+
+fn gdb_stub(args: &GdbArgs) -> Result<(), String> {
+    let Session {
+        mut comp, tickrate, ..
+    } = load(&args.emu)?;
+    gdb::serve(&mut comp, tickrate, args.port)
+}
 
-    Ok(tex)
+fn main() -> Result<(), String> {
+    let cli = Cli::parse();
+    match &cli.command {
+        None => run(&cli.run, false),
+        Some(Command::Run(args)) => run(args, false),
+        Some(Command::Debug(args)) => run(args, true),
+        Some(Command::Headless(args)) => headless(args),
+        Some(Command::Dasm(args)) => dasm(args),
+        Some(Command::Asm(args)) => assemble(args),
+        Some(Command::Info(args)) => info(args),
+        Some(Command::ImportDb(args)) => import_db(args),
+        Some(Command::Sprites(args)) => sprites(args),
+        Some(Command::Gdb(args)) => gdb_stub(args),
+        Some(Command::Dap) => dap::serve(std::io::stdin(), std::io::stdout()),
+    }
 }
@@ -0,0 +1,369 @@
+//! Input recordings that play a session back exactly, for rendering long
+//! runs offline. A movie is the ROM's hash, the random seed and quirks,
+//! then every input by the frame it came before:
+//!
+//! ```text
+//! chip8 movie
+//! rom 0a1b...
+//! seed 1234
+//! edge_mode clip
+//! key_wait_mode release
+//! length 3600
+//! 0 ticks 20
+//! 0 vblank off
+//! 120 down 5
+//! 126 up 5
+//! ```
+//!
+//! `ticks` is the instructions per frame from then on, `vblank on` runs
+//! whole frames even after a draw (see [`Chip8::frame`]), `step` runs a
+//! single instruction and `reset` is [`Chip8::reset`].
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::romdb::sha1_hex;
+use crate::{Chip8, EdgeMode, KeyWaitMode};
+
+const MAGIC: &str = "chip8 movie";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Down(usize),
+    Up(usize),
+    Ticks(u32),
+    Vblank(bool),
+    Step,
+    Reset,
+}
+
+pub struct Movie {
+    /// SHA-1 of the ROM it was recorded with.
+    pub rom: String,
+    pub seed: u64,
+    pub edge_mode: EdgeMode,
+    pub key_wait_mode: KeyWaitMode,
+    /// Frames run.
+    pub length: u32,
+    /// Events in order with the frame they came before.
+    pub events: Vec<(u32, Event)>,
+    /// Ticks and vblank as last recorded.
+    settings: Option<(u32, bool)>,
+}
+
+impl Movie {
+    /// Starts a movie of `comp`, which has to have just been loaded and
+    /// seeded with `seed`.
+    pub fn new(comp: &Chip8, seed: u64) -> Self {
+        Movie {
+            rom: sha1_hex(comp.rom()),
+            seed,
+            edge_mode: comp.edge_mode,
+            key_wait_mode: comp.key_wait_mode,
+            length: 0,
+            events: vec![],
+            settings: None,
+        }
+    }
+
+    /// Adds `event` before the next frame.
+    pub fn record(&mut self, event: Event) {
+        self.events.push((self.length, event));
+    }
+
+    /// Records the settings the next frame runs with, if they changed.
+    pub fn settings(&mut self, ticks: u32, vblank: bool) {
+        let prev = self.settings.replace((ticks, vblank));
+        if prev.map(|(t, _)| t) != Some(ticks) {
+            self.record(Event::Ticks(ticks));
+        }
+        if prev.map(|(_, v)| v) != Some(vblank) {
+            self.record(Event::Vblank(vblank));
+        }
+    }
+
+    /// Counts a frame as run.
+    pub fn next_frame(&mut self) {
+        self.length += 1;
+    }
+
+    /// A machine with `prog` loaded in the state the movie starts from.
+    pub fn machine(&self, prog: &[u8]) -> Result<Chip8, String> {
+        if sha1_hex(prog) != self.rom {
+            return Err(format!(
+                "The movie was recorded with another rom, {}",
+                self.rom
+            ));
+        }
+        let mut comp = Chip8::new()
+            .with_seed(self.seed)
+            .with_edge_mode(self.edge_mode)
+            .with_key_wait_mode(self.key_wait_mode);
+        comp.load(prog)?;
+        Ok(comp)
+    }
+
+    pub fn player(&self) -> Player<'_> {
+        Player {
+            movie: self,
+            next: 0,
+            frame: 0,
+            ticks: 20,
+            vblank: false,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Movie::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_text()).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{MAGIC}\n");
+        let _ = writeln!(out, "rom {}", self.rom);
+        let _ = writeln!(out, "seed {}", self.seed);
+        let edge_mode = match self.edge_mode {
+            EdgeMode::Clip => "clip",
+            EdgeMode::Wrap => "wrap",
+        };
+        let _ = writeln!(out, "edge_mode {edge_mode}");
+        let key_wait_mode = match self.key_wait_mode {
+            KeyWaitMode::Release => "release",
+            KeyWaitMode::Press => "press",
+        };
+        let _ = writeln!(out, "key_wait_mode {key_wait_mode}");
+        let _ = writeln!(out, "length {}", self.length);
+
+        for (frame, event) in &self.events {
+            let _ = match event {
+                Event::Down(key) => writeln!(out, "{frame} down {key:x}"),
+                Event::Up(key) => writeln!(out, "{frame} up {key:x}"),
+                Event::Ticks(ticks) => writeln!(out, "{frame} ticks {ticks}"),
+                Event::Vblank(on) => {
+                    writeln!(out, "{frame} vblank {}", if *on { "on" } else { "off" })
+                }
+                Event::Step => writeln!(out, "{frame} step"),
+                Event::Reset => writeln!(out, "{frame} reset"),
+            };
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, l)| l.trim()) != Some(MAGIC) {
+            return Err(format!(
+                "not a movie, expected it to start with \"{MAGIC}\""
+            ));
+        }
+
+        let mut movie = Movie {
+            rom: String::new(),
+            seed: 0,
+            edge_mode: EdgeMode::Clip,
+            key_wait_mode: KeyWaitMode::Release,
+            length: 0,
+            events: vec![],
+            settings: None,
+        };
+
+        for (idx, line) in lines {
+            let err = |e: &str| format!("line {}: {e}", idx + 1);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let num = |s: &str| {
+                s.parse::<u64>()
+                    .map_err(|_| err(&format!("bad number {s}")))
+            };
+            let key = |s: &str| match usize::from_str_radix(s, 16) {
+                Ok(key) if key < 16 => Ok(key),
+                _ => Err(err(&format!("bad key {s}"))),
+            };
+
+            match words.as_slice() {
+                [] => {}
+                ["rom", hash] => movie.rom = hash.to_string(),
+                ["seed", seed] => movie.seed = num(seed)?,
+                ["edge_mode", "clip"] => movie.edge_mode = EdgeMode::Clip,
+                ["edge_mode", "wrap"] => movie.edge_mode = EdgeMode::Wrap,
+                ["key_wait_mode", "release"] => movie.key_wait_mode = KeyWaitMode::Release,
+                ["key_wait_mode", "press"] => movie.key_wait_mode = KeyWaitMode::Press,
+                ["length", n] => movie.length = num(n)? as u32,
+                [frame, event @ ..] => {
+                    let frame = num(frame)? as u32;
+                    let event = match event {
+                        ["down", k] => Event::Down(key(k)?),
+                        ["up", k] => Event::Up(key(k)?),
+                        ["ticks", n] => Event::Ticks(num(n)? as u32),
+                        ["vblank", "on"] => Event::Vblank(true),
+                        ["vblank", "off"] => Event::Vblank(false),
+                        ["step"] => Event::Step,
+                        ["reset"] => Event::Reset,
+                        _ => return Err(err(&format!("can't parse {line}"))),
+                    };
+                    if movie.events.last().is_some_and(|(last, _)| *last > frame) {
+                        return Err(err("events are out of order"));
+                    }
+                    movie.events.push((frame, event));
+                }
+            }
+        }
+
+        if movie.rom.is_empty() {
+            return Err("missing the rom hash".into());
+        }
+        Ok(movie)
+    }
+}
+
+/// Plays a [`Movie`] back a frame at a time.
+pub struct Player<'a> {
+    movie: &'a Movie,
+    /// Index of the next event.
+    next: usize,
+    frame: u32,
+    ticks: u32,
+    vblank: bool,
+}
+
+impl Player<'_> {
+    /// Whether every recorded frame has been played.
+    pub fn done(&self) -> bool {
+        self.frame >= self.movie.length
+    }
+
+    /// Applies the events before the next frame and runs it. Past the end
+    /// it keeps running with no more input.
+    pub fn frame(&mut self, comp: &mut Chip8) -> Result<(), String> {
+        let events = &self.movie.events[self.next..];
+        for (_, event) in events.iter().take_while(|(frame, _)| *frame <= self.frame) {
+            self.next += 1;
+            match *event {
+                Event::Down(key) => comp.key_down(key),
+                Event::Up(key) => comp.key_up(key),
+                Event::Ticks(ticks) => self.ticks = ticks,
+                Event::Vblank(on) => self.vblank = on,
+                Event::Step => {
+                    comp.step()?;
+                    comp.draw = false;
+                }
+                Event::Reset => comp.reset(),
+            }
+        }
+
+        self.frame += 1;
+        comp.frame(self.ticks, !self.vblank)?;
+        // the frontend clears it once the screen is shown, and a frame
+        // stops early when it's still set
+        comp.draw = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Waits for a key, then draws a 8x2 bar at the key's column and a
+    /// random row.
+    const PROG: [u8; 14] = [
+        0xf0, 0x0a, 0xc1, 0xff, 0xa2, 0x0c, 0xd0, 0x12, 0x12, 0x08, 0x00, 0x00, 0xff, 0xff,
+    ];
+
+    /// A movie of `PROG` pressing 5 on frame 2 and letting go on frame 4.
+    fn movie() -> Movie {
+        let mut comp = Chip8::new().with_seed(7);
+        comp.load(&PROG).unwrap();
+        let mut movie = Movie::new(&comp, 7);
+        for frame in 0..10 {
+            movie.settings(10, false);
+            match frame {
+                2 => movie.record(Event::Down(5)),
+                4 => movie.record(Event::Up(5)),
+                _ => {}
+            }
+            movie.next_frame();
+        }
+        movie
+    }
+
+    #[test]
+    fn records_settings_only_when_they_change() {
+        let movie = movie();
+        assert_eq!(movie.length, 10);
+        assert_eq!(
+            movie.events,
+            vec![
+                (0, Event::Ticks(10)),
+                (0, Event::Vblank(false)),
+                (2, Event::Down(5)),
+                (4, Event::Up(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let mut movie = movie();
+        movie.edge_mode = EdgeMode::Wrap;
+        movie.key_wait_mode = KeyWaitMode::Press;
+        movie.events.push((9, Event::Vblank(true)));
+        movie.events.push((9, Event::Step));
+        movie.events.push((9, Event::Reset));
+        movie.events.push((9, Event::Down(0xf)));
+
+        let text = movie.to_text();
+        assert!(text.starts_with("chip8 movie\n"));
+        assert!(text.contains("\n9 down f\n"));
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed.rom, movie.rom);
+        assert_eq!(parsed.seed, 7);
+        assert_eq!(parsed.edge_mode, EdgeMode::Wrap);
+        assert_eq!(parsed.key_wait_mode, KeyWaitMode::Press);
+        assert_eq!(parsed.length, 10);
+        assert_eq!(parsed.events, movie.events);
+        assert_eq!(parsed.to_text(), text);
+    }
+
+    #[test]
+    fn parse_rejects_bad_movies() {
+        assert!(Movie::parse("rom abc\n").is_err());
+        assert!(Movie::parse("chip8 movie\nseed 1\n").is_err());
+        assert!(Movie::parse("chip8 movie\nrom abc\n0 down 10\n").is_err());
+        assert!(Movie::parse("chip8 movie\nrom abc\n3 down 1\n2 up 1\n").is_err());
+        assert!(Movie::parse("chip8 movie\nrom abc\n0 jump\n").is_err());
+    }
+
+    #[test]
+    fn machine_checks_the_rom() {
+        let movie = movie();
+        assert!(movie.machine(&PROG[..12]).is_err());
+        assert!(movie.machine(&PROG).is_ok());
+    }
+
+    #[test]
+    fn playing_back_is_deterministic() {
+        let movie = Movie::parse(&movie().to_text()).unwrap();
+        let play = || {
+            let mut comp = movie.machine(&PROG).unwrap();
+            let mut player = movie.player();
+            while !player.done() {
+                player.frame(&mut comp).unwrap();
+            }
+            comp
+        };
+
+        let first = play();
+        let second = play();
+        assert_eq!(first.regs(), second.regs());
+        assert_eq!(first.gfx, second.gfx);
+
+        // the key went through and the bar is drawn
+        let (x, y) = (5, first.regs()[1] as usize % first.height());
+        assert_eq!(first.regs()[0], 5);
+        assert_ne!(first.gfx[y * first.width() + x], 0);
+    }
+}
@@ -0,0 +1,150 @@
+//! Screen colors. A palette has four entries so it can color XO-CHIP's two
+//! bitplanes: background, plane 1, plane 2 and both planes. Plain CHIP-8
+//! only ever uses the first two.
+
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [u32; 4],
+}
+
+/// Built in palettes, in the order they're cycled through.
+const PRESETS: [(&str, [u32; 4]); 5] = [
+    ("classic", [0x000000, 0xffffff, 0xaaaaaa, 0x555555]),
+    ("amber", [0x1a0f00, 0xffb000, 0xb37b00, 0x664600]),
+    ("green", [0x001a05, 0x33ff66, 0x22aa44, 0x115522]),
+    ("lcd", [0x9bbc0f, 0x0f380f, 0x306230, 0x8bac0f]),
+    ("contrast", [0x000000, 0xffff00, 0x00ffff, 0xff00ff]),
+];
+
+impl Palette {
+    pub fn new(name: &str, colors: [u32; 4]) -> Self {
+        Palette {
+            name: name.to_owned(),
+            colors,
+        }
+    }
+
+    pub fn presets() -> Vec<Palette> {
+        PRESETS
+            .iter()
+            .map(|(name, colors)| Palette::new(name, *colors))
+            .collect()
+    }
+
+    pub fn preset(name: &str) -> Option<Palette> {
+        Palette::presets().into_iter().find(|p| p.name == name)
+    }
+
+    /// Builds a palette from 2 to 4 color strings. Missing plane colors are
+    /// taken from the classic palette.
+    pub fn parse(name: &str, colors: &[String]) -> Result<Palette, String> {
+        if colors.len() < 2 || colors.len() > 4 {
+            return Err(format!(
+                "Palette {name} needs 2 to 4 colors, got {}",
+                colors.len()
+            ));
+        }
+
+        let mut palette = Palette::new(name, PRESETS[0].1);
+        for (i, c) in colors.iter().enumerate() {
+            palette.colors[i] = parse_color(c)?;
+        }
+
+        Ok(palette)
+    }
+
+    pub fn bg(&self) -> u32 {
+        self.colors[0]
+    }
+
+    pub fn fg(&self) -> u32 {
+        self.colors[1]
+    }
+
+    pub fn rgb(&self) -> [[u8; 3]; 4] {
+        self.colors.map(rgb)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new(PRESETS[0].0, PRESETS[0].1)
+    }
+}
+
+impl Display for Palette {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Parses `rrggbb` or `rgb` hex, optionally prefixed with `#` or `0x`.
+pub fn parse_color(s: &str) -> Result<u32, String> {
+    let hex = s
+        .strip_prefix('#')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid color {s:?}, expected hex like ff8800"));
+    }
+
+    match hex.len() {
+        6 => Ok(u32::from_str_radix(hex, 16).unwrap()),
+        3 => {
+            let c = u32::from_str_radix(hex, 16).unwrap();
+            let (r, g, b) = (c >> 8 & 0xf, c >> 4 & 0xf, c & 0xf);
+            Ok((r * 0x11) << 16 | (g * 0x11) << 8 | (b * 0x11))
+        }
+        _ => Err(format!("Invalid color {s:?}, expected 3 or 6 hex digits")),
+    }
+}
+
+pub fn rgb(c: u32) -> [u8; 3] {
+    [
+        (c >> 16 & 0xff) as u8,
+        (c >> 8 & 0xff) as u8,
+        (c & 0xff) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(parse_color("ff8800"), Ok(0xff8800));
+        assert_eq!(parse_color("#FF8800"), Ok(0xff8800));
+        assert_eq!(parse_color("0x0a0b0c"), Ok(0x0a0b0c));
+        assert_eq!(parse_color("#f80"), Ok(0xff8800));
+    }
+
+    #[test]
+    fn rejects_invalid_colors() {
+        for s in ["", "#", "ff88", "ff88001", "gg8800", "+f8800", "#-f8"] {
+            assert!(parse_color(s).is_err(), "{s:?} parsed");
+        }
+    }
+
+    #[test]
+    fn parse_fills_missing_planes_from_classic() {
+        let colors = ["#123456".to_owned(), "abc".to_owned()];
+        let palette = Palette::parse("mine", &colors).unwrap();
+        assert_eq!(palette.colors, [0x123456, 0xaabbcc, 0xaaaaaa, 0x555555]);
+        assert_eq!(palette.bg(), 0x123456);
+        assert_eq!(palette.fg(), 0xaabbcc);
+    }
+
+    #[test]
+    fn parse_checks_the_color_count_and_each_color() {
+        let colors = |c: &[&str]| c.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(Palette::parse("one", &colors(&["000"])).is_err());
+        assert!(Palette::parse("five", &colors(&["000"; 5])).is_err());
+        assert!(Palette::parse("bad", &colors(&["000", "nope"])).is_err());
+        assert!(Palette::parse("four", &colors(&["000"; 4])).is_ok());
+    }
+}
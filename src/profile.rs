@@ -0,0 +1,229 @@
+//! Where the instructions go: counts by address and by opcode, and per
+//! subroutine through `Call` and `Ret`. A cycle here is one instruction,
+//! as in [`Chip8::cycles`](crate::Chip8).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::{Chip8, OpCode, PROG_OFFSET};
+
+/// Instructions run by a subroutine.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Routine {
+    pub calls: u64,
+    /// Including the routines it calls.
+    pub inclusive: u64,
+    /// Only its own.
+    pub exclusive: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub total: u64,
+    /// Instructions run at each address.
+    pub pcs: BTreeMap<usize, u64>,
+    /// Instructions run of each kind, by [`OpCode::name`].
+    pub ops: BTreeMap<&'static str, u64>,
+    /// By entry address, the program's start standing for the main loop.
+    pub routines: BTreeMap<usize, Routine>,
+    /// Instructions run under each chain of calls, outermost first.
+    pub stacks: HashMap<Vec<usize>, u64>,
+    /// Entry addresses of the routines running, outermost first.
+    calls: Vec<usize>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            total: 0,
+            pcs: BTreeMap::new(),
+            ops: BTreeMap::new(),
+            routines: BTreeMap::new(),
+            stacks: HashMap::new(),
+            calls: vec![PROG_OFFSET],
+        }
+    }
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    /// Counts `op` at `pc`, which is about to run.
+    pub fn record(&mut self, pc: usize, op: &OpCode) {
+        self.total += 1;
+        *self.pcs.entry(pc).or_default() += 1;
+        *self.ops.entry(op.name()).or_default() += 1;
+
+        let current = *self.calls.last().unwrap_or(&PROG_OFFSET);
+        self.routines.entry(current).or_default().exclusive += 1;
+        for (i, addr) in self.calls.iter().enumerate() {
+            // a recursive routine only counts once
+            if !self.calls[..i].contains(addr) {
+                self.routines.entry(*addr).or_default().inclusive += 1;
+            }
+        }
+        match self.stacks.get_mut(self.calls.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.calls.clone(), 1);
+            }
+        }
+
+        match *op {
+            OpCode::Call(addr) => {
+                self.routines.entry(addr).or_default().calls += 1;
+                self.calls.push(addr);
+            }
+            // more returns than calls stay in the main loop
+            OpCode::Ret if self.calls.len() > 1 => {
+                self.calls.pop();
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets the calls in progress, for when the machine is reset.
+    pub fn reset_calls(&mut self) {
+        self.calls.truncate(1);
+    }
+
+    /// `main` for the program's start, otherwise `sub_<addr>`.
+    pub fn name(addr: usize) -> String {
+        match addr {
+            PROG_OFFSET => "main".to_owned(),
+            _ => format!("sub_{addr:03x}"),
+        }
+    }
+
+    /// The call chains in the folded format flame graph tools read, one
+    /// `main;sub_2a4;sub_300 123` line each.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let names: Vec<String> = stack.iter().map(|a| Profile::name(*a)).collect();
+                format!("{} {count}\n", names.join(";"))
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    /// The `top` busiest addresses, with the instruction now in `mem` at
+    /// each, and every opcode and routine.
+    pub fn report(&self, mem: &[u8], top: usize) -> String {
+        let pct = |n: u64| n as f64 * 100.0 / self.total.max(1) as f64;
+        let mut out = String::new();
+        let _ = writeln!(out, "{} instructions\n", self.total);
+
+        let mut pcs: Vec<(&usize, &u64)> = self.pcs.iter().collect();
+        pcs.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let _ = writeln!(out, "addr  {:>10}  {:>6}  instruction", "count", "%");
+        for (addr, count) in pcs.into_iter().take(top) {
+            let op = match mem.get(*addr..addr + 2) {
+                Some(word) => Chip8::decode((word[0] as u16) << 8 | word[1] as u16).to_string(),
+                None => String::new(),
+            };
+            let _ = writeln!(out, "{addr:03x}   {count:>10}  {:>5.1}%  {op}", pct(*count));
+        }
+
+        let mut ops: Vec<(&&str, &u64)> = self.ops.iter().collect();
+        ops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let _ = writeln!(out, "\n{:<10}  {:>10}  {:>6}", "opcode", "count", "%");
+        for (name, count) in ops {
+            let _ = writeln!(out, "{name:<10}  {count:>10}  {:>5.1}%", pct(*count));
+        }
+
+        let mut routines: Vec<(&usize, &Routine)> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        let _ = writeln!(
+            out,
+            "\n{:<8}  {:>8}  {:>10}  {:>6}  {:>10}  {:>6}",
+            "routine", "calls", "inclusive", "%", "exclusive", "%"
+        );
+        for (addr, r) in routines {
+            let _ = writeln!(
+                out,
+                "{:<8}  {:>8}  {:>10}  {:>5.1}%  {:>10}  {:>5.1}%",
+                Profile::name(*addr),
+                r.calls,
+                r.inclusive,
+                pct(r.inclusive),
+                r.exclusive,
+                pct(r.exclusive)
+            );
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `main`, which calls `sub_206`, which calls `sub_20c`, then
+    /// spins in place.
+    fn profile() -> Profile {
+        let prog = [
+            0x22, 0x06, // 200: call 206
+            0x12, 0x02, // 202: jmp 202
+            0x00, 0x00, //
+            0x60, 0x01, // 206: v0 = 1
+            0x22, 0x0c, // 208: call 20c
+            0x00, 0xee, // 20a: ret
+            0x61, 0x02, // 20c: v1 = 2
+            0x00, 0xee, // 20e: ret
+        ];
+        let mut comp = Chip8::new().with_profile();
+        comp.load(&prog).unwrap();
+        for _ in 0..8 {
+            comp.step().unwrap();
+        }
+        comp.profile.unwrap()
+    }
+
+    #[test]
+    fn counts_by_address_and_opcode() {
+        let profile = profile();
+        assert_eq!(profile.total, 8);
+        assert_eq!(profile.pcs[&0x202], 2);
+        assert_eq!(profile.pcs[&0x20c], 1);
+        assert_eq!(profile.ops["Call"], 2);
+        assert_eq!(profile.ops["Ret"], 2);
+        assert_eq!(profile.ops["Jmp"], 2);
+    }
+
+    #[test]
+    fn inclusive_counts_the_routines_called() {
+        let profile = profile();
+        let counts = |addr: usize| {
+            let r = profile.routines[&addr];
+            (r.calls, r.inclusive, r.exclusive)
+        };
+        assert_eq!(counts(0x200), (0, 8, 3));
+        assert_eq!(counts(0x206), (1, 5, 3));
+        assert_eq!(counts(0x20c), (1, 2, 2));
+    }
+
+    #[test]
+    fn folded_has_a_line_per_call_chain() {
+        assert_eq!(
+            profile().folded(),
+            "main 3\nmain;sub_206 3\nmain;sub_206;sub_20c 2\n"
+        );
+    }
+
+    #[test]
+    fn reset_goes_back_to_main() {
+        let mut profile = Profile::new();
+        profile.record(0x200, &OpCode::Call(0x300));
+        profile.reset_calls();
+        profile.record(0x200, &OpCode::Jmp(0x200));
+        assert_eq!(profile.routines[&0x200].exclusive, 2);
+        assert_eq!(profile.routines[&0x300].inclusive, 0);
+    }
+}
@@ -0,0 +1,300 @@
+//! Per ROM settings, keyed by the SHA-1 of the ROM bytes so they follow the
+//! ROM whatever its file is called.
+//!
+//! ```toml
+//! [roms.e18b6ee2be2d6e3d7a4fbf1ec3d8c1a2f1d7c6e5]
+//! name = "Pong"
+//! platform = "chip8"
+//! tickrate = 10
+//! palette = "green"
+//!
+//! [roms.e18b6ee2be2d6e3d7a4fbf1ec3d8c1a2f1d7c6e5.keys]
+//! 1 = ["1", "up"]
+//! 4 = ["q", "down"]
+//! ```
+//!
+//! Entries can be imported from the `programs.json` of the community
+//! CHIP-8 database, see [`RomDb::import_community`].
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{EdgeMode, KeyWaitMode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    /// COSMAC VIP CHIP-8.
+    Chip8,
+    /// CHIP-48 and SUPER-CHIP on the HP48.
+    Schip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn edge_mode(self) -> EdgeMode {
+        match self {
+            Platform::Chip8 | Platform::Schip => EdgeMode::Clip,
+            Platform::XoChip => EdgeMode::Wrap,
+        }
+    }
+
+    pub fn key_wait_mode(self) -> KeyWaitMode {
+        match self {
+            Platform::Chip8 => KeyWaitMode::Release,
+            Platform::Schip | Platform::XoChip => KeyWaitMode::Press,
+        }
+    }
+
+    /// Platform for a community database platform id.
+    fn from_community(id: &str) -> Option<Platform> {
+        match id {
+            "originalChip8" | "hybridVIP" | "chip8x" => Some(Platform::Chip8),
+            "modernChip8" | "chip48" | "superchip1" | "superchip" => Some(Platform::Schip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Sets the quirks below unless they're given explicitly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tickrate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edge_mode: Option<EdgeMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_wait_mode: Option<KeyWaitMode>,
+    /// Name of a palette, see [`Config::palettes`](crate::config::Config::palettes).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
+    /// Palette colors for this ROM, takes precedence over `palette`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub colors: Vec<String>,
+    /// Extra key bindings, added to the configured ones.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, Vec<String>>,
+}
+
+impl RomEntry {
+    pub fn edge_mode(&self) -> Option<EdgeMode> {
+        self.edge_mode
+            .or_else(|| self.platform.map(Platform::edge_mode))
+    }
+
+    pub fn key_wait_mode(&self) -> Option<KeyWaitMode> {
+        self.key_wait_mode
+            .or_else(|| self.platform.map(Platform::key_wait_mode))
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomDb {
+    /// Entries by lowercase hex SHA-1.
+    pub roms: BTreeMap<String, RomEntry>,
+}
+
+impl RomDb {
+    /// Loads `path`, which has to exist, or the default database if there
+    /// is one.
+    pub fn load(path: Option<&Path>) -> Result<RomDb, String> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(RomDb::default()),
+            },
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read rom db {}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid rom db {}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, text)
+            .map_err(|e| format!("Failed to write rom db {}: {e}", path.display()))
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomEntry> {
+        self.roms.get(&sha1_hex(rom))
+    }
+
+    /// Adds the entries of a community database `programs.json`, keeping
+    /// any existing entry for the same hash. Returns how many were added.
+    pub fn import_community(&mut self, programs_json: &str) -> Result<usize, String> {
+        let programs: Vec<Value> = serde_json::from_str(programs_json)
+            .map_err(|e| format!("Invalid programs.json: {e}"))?;
+
+        let mut added = 0;
+        for program in &programs {
+            let title = program["title"].as_str();
+            let Some(roms) = program["roms"].as_object() else {
+                continue;
+            };
+
+            for (hash, rom) in roms {
+                let hash = hash.to_lowercase();
+                if self.roms.contains_key(&hash) {
+                    continue;
+                }
+
+                self.roms.insert(hash, community_entry(title, rom));
+                added += 1;
+            }
+        }
+
+        Ok(added)
+    }
+}
+
+/// Community database key actions and the host keys they're bound to, on
+/// top of the key's configured bindings.
+const COMMUNITY_KEYS: [(&str, [&str; 2]); 6] = [
+    ("up", ["up", "pad_up"]),
+    ("down", ["down", "pad_down"]),
+    ("left", ["left", "pad_left"]),
+    ("right", ["right", "pad_right"]),
+    ("a", ["space", "pad_a"]),
+    ("b", ["enter", "pad_b"]),
+];
+
+fn community_entry(title: Option<&str>, rom: &Value) -> RomEntry {
+    let platform = rom["platforms"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .find_map(Platform::from_community);
+
+    let colors = rom["colors"]["pixels"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .take(4)
+        .map(str::to_owned)
+        .collect();
+
+    let mut keys: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (action, names) in COMMUNITY_KEYS {
+        let Some(key) = rom["keys"][action].as_u64().filter(|k| *k < 16) else {
+            continue;
+        };
+
+        keys.entry(format!("{key:x}"))
+            .or_default()
+            .extend(names.iter().map(|n| n.to_string()));
+    }
+
+    RomEntry {
+        name: title.map(str::to_owned),
+        platform,
+        tickrate: rom["tickrate"].as_u64().map(|t| t as u32),
+        colors,
+        keys,
+        ..Default::default()
+    }
+}
+
+pub fn sha1_hex(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+/// `roms.toml` next to the config file.
+pub fn default_path() -> Option<PathBuf> {
+    crate::config::default_path().map(|path| path.with_file_name("roms.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two programs in the community database's format, the first with one
+    /// ROM whose bytes are "abc".
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Pong",
+            "roms": {
+                "A9993E364706816ABA3E25717850C26C9CD0D89D": {
+                    "platforms": ["modernChip8", "originalChip8"],
+                    "tickrate": 15,
+                    "colors": { "pixels": ["#000000", "#33ff66"] },
+                    "keys": { "up": 1, "down": 4, "a": 16 }
+                }
+            }
+        },
+        { "title": "No roms" }
+    ]"##;
+
+    #[test]
+    fn import_community_converts_entries() {
+        let mut db = RomDb::default();
+        assert_eq!(db.import_community(PROGRAMS), Ok(1));
+
+        let entry = db.lookup(b"abc").unwrap();
+        assert_eq!(entry.name.as_deref(), Some("Pong"));
+        assert_eq!(entry.platform, Some(Platform::Schip));
+        assert_eq!(entry.tickrate, Some(15));
+        assert_eq!(entry.colors, ["#000000", "#33ff66"]);
+        assert_eq!(entry.keys["1"], ["up", "pad_up"]);
+        assert_eq!(entry.keys["4"], ["down", "pad_down"]);
+        // key 16 isn't on the keypad
+        assert_eq!(entry.keys.len(), 2);
+        assert_eq!(entry.key_wait_mode(), Some(KeyWaitMode::Press));
+    }
+
+    #[test]
+    fn import_community_keeps_existing_entries() {
+        let mut db = RomDb::default();
+        let mine = RomEntry {
+            name: Some("mine".to_owned()),
+            ..Default::default()
+        };
+        db.roms.insert(sha1_hex(b"abc"), mine);
+
+        assert_eq!(db.import_community(PROGRAMS), Ok(0));
+        assert_eq!(db.lookup(b"abc").unwrap().name.as_deref(), Some("mine"));
+    }
+
+    #[test]
+    fn import_community_rejects_invalid_json() {
+        assert!(RomDb::default().import_community("{").is_err());
+    }
+
+    #[test]
+    fn lookup_is_by_sha1() {
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+        let db: RomDb = toml::from_str(
+            r#"
+            [roms.a9993e364706816aba3e25717850c26c9cd0d89d]
+            name = "Pong"
+            edge_mode = "wrap"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(db.lookup(b"abc").unwrap().edge_mode(), Some(EdgeMode::Wrap));
+        assert!(db.lookup(b"xyz").is_none());
+    }
+
+    #[test]
+    fn load_fails_for_a_missing_explicit_path() {
+        let path = Path::new("/nonexistent/roms.toml");
+        assert!(RomDb::load(Some(path)).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! Debug layout: the game moves to the top left of the window and panels
+//! around it show the registers, stack, timers, keypad, a disassembly around
+//! `pc` and memory around `I`, or the sprites drawn so far in its place.
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+
+use super::draw_text;
+use crate::font;
+use crate::frontend::FrameState;
+use crate::keymap::KEYPAD;
+use crate::sprites::{self, SpriteSize};
+use crate::Chip8;
+
+const TITLE: Color = Color::RGB(140, 140, 140);
+const TEXT: Color = Color::RGB(220, 220, 220);
+/// Bytes `I` points at, and held keys.
+const INDEX: Color = Color::RGB(255, 176, 0);
+/// The instruction at `pc`.
+const PC: Color = Color::RGB(80, 200, 255);
+/// Behind sprites, so their empty pixels show.
+const CELL: Color = Color::RGB(40, 40, 40);
+
+/// Bytes highlighted from `I` on, enough for the largest sprite.
+const INDEX_SPAN: usize = 16;
+
+pub struct Debugger {
+    pub visible: bool,
+    /// Shows the sprites drawn instead of memory.
+    pub sprites: bool,
+    regs: [u8; 16],
+    index: usize,
+    pc: usize,
+    stack: Vec<usize>,
+    timers: (u8, u8),
+    keys: u16,
+    cycles: u32,
+    mem: Vec<u8>,
+    /// Addresses drawn from and the sprite height.
+    drawn: Vec<(usize, u8)>,
+}
+
+/// Text position and size on the window.
+struct Pen {
+    scale: u32,
+    x: i32,
+    y: i32,
+}
+
+impl Pen {
+    fn char_width(&self) -> i32 {
+        (font::ADVANCE as u32 * self.scale) as i32
+    }
+
+    fn line_height(&self) -> i32 {
+        ((font::GLYPH_HEIGHT + 2) as u32 * self.scale) as i32
+    }
+
+    /// Draws `text` at column `col` of the current line.
+    fn text(
+        &self,
+        canvas: &mut WindowCanvas,
+        col: i32,
+        text: &str,
+        color: Color,
+    ) -> Result<(), String> {
+        let x = self.x + col * self.char_width();
+        draw_text(canvas, text, x, self.y, self.scale, color)
+    }
+
+    fn newline(&mut self) {
+        self.y += self.line_height();
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            visible: false,
+            sprites: false,
+            regs: [0; 16],
+            index: 0,
+            pc: 0,
+            stack: vec![],
+            timers: (0, 0),
+            keys: 0,
+            cycles: 0,
+            mem: vec![],
+            drawn: vec![],
+        }
+    }
+
+    pub fn update(&mut self, state: &FrameState) {
+        let comp = state.comp;
+        self.regs.copy_from_slice(comp.regs());
+        self.index = comp.index();
+        self.pc = comp.pc();
+        self.stack = comp.stack();
+        self.timers = (comp.delay_timer(), comp.sound_timer());
+        self.keys = comp.keys();
+        self.cycles = comp.cycles;
+        self.mem.clear();
+        self.mem.extend_from_slice(comp.mem());
+        self.drawn.clear();
+        self.drawn
+            .extend(comp.sprites().iter().map(|(addr, n)| (*addr, *n)));
+    }
+
+    fn scale(win_w: u32, win_h: u32) -> u32 {
+        (win_w / 400).min(win_h / 200).max(1)
+    }
+
+    /// Where the game goes while the debugger is shown.
+    pub fn game_rect(win_w: u32, win_h: u32) -> Rect {
+        let w = win_w * 3 / 5;
+        Rect::new(0, 0, w.max(1), (w / 2).min(win_h * 3 / 5).max(1))
+    }
+
+    pub fn draw(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let (win_w, win_h) = canvas.output_size()?;
+        let game = Debugger::game_rect(win_w, win_h);
+        let scale = Debugger::scale(win_w, win_h);
+        let margin = (4 * scale) as i32;
+
+        let mut pen = Pen {
+            scale,
+            x: game.right() + margin,
+            y: margin,
+        };
+        self.draw_cpu(canvas, &mut pen)?;
+        pen.newline();
+        self.draw_code(canvas, &mut pen, win_h as i32 - margin)?;
+
+        let mut pen = Pen {
+            scale,
+            x: margin,
+            y: game.bottom() + margin,
+        };
+        let bottom = win_h as i32 - margin;
+        if self.sprites {
+            let right = game.right() - margin;
+            return self.draw_sprites(canvas, &mut pen, right, bottom);
+        }
+        let columns = (game.width() as i32 - margin * 2) / pen.char_width();
+        self.draw_memory(canvas, &mut pen, columns, bottom)
+    }
+
+    fn draw_cpu(&self, canvas: &mut WindowCanvas, pen: &mut Pen) -> Result<(), String> {
+        pen.text(canvas, 0, "CPU", TITLE)?;
+        pen.newline();
+
+        for (row, regs) in self.regs.chunks(4).enumerate() {
+            for (col, val) in regs.iter().enumerate() {
+                let text = format!("V{:X} {:02X}", row * 4 + col, val);
+                pen.text(canvas, col as i32 * 6, &text, TEXT)?;
+            }
+            pen.newline();
+        }
+
+        let text = format!("PC {:03X}  I {:03X}", self.pc, self.index);
+        pen.text(canvas, 0, &text, TEXT)?;
+        pen.newline();
+        let text = format!("DT {:02X}  ST {:02X}", self.timers.0, self.timers.1);
+        pen.text(canvas, 0, &text, TEXT)?;
+        pen.newline();
+        pen.text(canvas, 0, &format!("CYCLES {}", self.cycles), TEXT)?;
+        pen.newline();
+
+        let stack: Vec<String> = self.stack.iter().map(|a| format!("{a:03X}")).collect();
+        pen.text(canvas, 0, &format!("STACK {}", self.stack.len()), TITLE)?;
+        pen.text(canvas, 9, &stack.join(" "), TEXT)?;
+        pen.newline();
+        pen.newline();
+
+        pen.text(canvas, 0, "KEYS", TITLE)?;
+        let top = pen.y;
+        for (pos, key) in KEYPAD.iter().enumerate() {
+            pen.y = top + (pos / 4) as i32 * pen.line_height();
+            let held = self.keys >> key & 1 == 1;
+            let color = if held { INDEX } else { TEXT };
+            pen.text(canvas, 6 + (pos % 4) as i32 * 2, &format!("{key:X}"), color)?;
+        }
+        pen.newline();
+
+        Ok(())
+    }
+
+    fn draw_code(
+        &self,
+        canvas: &mut WindowCanvas,
+        pen: &mut Pen,
+        bottom: i32,
+    ) -> Result<(), String> {
+        pen.text(canvas, 0, "CODE", TITLE)?;
+        pen.newline();
+
+        let rows = ((bottom - pen.y) / pen.line_height()).max(0) as usize;
+        // a third of the rows before pc, stepping back by whole instructions
+        let start = self.pc.saturating_sub(rows / 3 * 2);
+        for addr in (start..).step_by(2).take(rows) {
+            let Some(word) = self.mem.get(addr..addr + 2) else {
+                break;
+            };
+            let raw = (word[0] as u16) << 8 | word[1] as u16;
+            let here = addr == self.pc;
+            let text = format!(
+                "{}{:03X} {:04X} {}",
+                if here { ">" } else { " " },
+                addr,
+                raw,
+                Chip8::decode(raw)
+            );
+            pen.text(canvas, 0, &text, if here { PC } else { TEXT })?;
+            pen.newline();
+        }
+
+        Ok(())
+    }
+
+    fn draw_memory(
+        &self,
+        canvas: &mut WindowCanvas,
+        pen: &mut Pen,
+        columns: i32,
+        bottom: i32,
+    ) -> Result<(), String> {
+        pen.text(canvas, 0, "MEMORY", TITLE)?;
+        pen.newline();
+
+        // 4 columns for the address, 3 for each byte
+        let per_row = if columns >= 4 + 16 * 3 { 16 } else { 8 };
+        let rows = ((bottom - pen.y) / pen.line_height()).max(0) as usize;
+        let total = self.mem.len() / per_row;
+        let first = (self.index / per_row)
+            .saturating_sub(rows / 3)
+            .min(total.saturating_sub(rows));
+
+        for row in first..(first + rows).min(total) {
+            let addr = row * per_row;
+            pen.text(canvas, 0, &format!("{addr:03X}"), TITLE)?;
+            for (col, byte) in self.mem[addr..addr + per_row].iter().enumerate() {
+                let at = addr + col;
+                let color = if (self.index..self.index + INDEX_SPAN).contains(&at) {
+                    INDEX
+                } else if (self.pc..self.pc + 2).contains(&at) {
+                    PC
+                } else {
+                    TEXT
+                };
+                pen.text(canvas, 4 + col as i32 * 3, &format!("{byte:02X}"), color)?;
+            }
+            pen.newline();
+        }
+
+        Ok(())
+    }
+
+    /// Every sprite drawn so far at its address, the one at `I` outlined.
+    fn draw_sprites(
+        &self,
+        canvas: &mut WindowCanvas,
+        pen: &mut Pen,
+        right: i32,
+        bottom: i32,
+    ) -> Result<(), String> {
+        pen.text(canvas, 0, &format!("SPRITES {}", self.drawn.len()), TITLE)?;
+        pen.newline();
+
+        let px = pen.scale as i32 * 2;
+        let gap = pen.char_width();
+        let tallest = self
+            .drawn
+            .iter()
+            .map(|(_, n)| SpriteSize::from_height(*n).height());
+        let row_height = pen.line_height() + tallest.max().unwrap_or(0) as i32 * px + gap;
+        let (left, mut x) = (pen.x, pen.x);
+
+        for &(addr, n) in &self.drawn {
+            let size = SpriteSize::from_height(n);
+            let (w, h) = (size.width() as i32 * px, size.height() as i32 * px);
+            let cell_w = w.max(3 * pen.char_width());
+            if x + cell_w > right && x > left {
+                x = left;
+                pen.y += row_height;
+            }
+            if pen.y + row_height - gap > bottom {
+                break;
+            }
+
+            let at_index = addr == self.index;
+            let label = Pen { x, ..*pen };
+            label.text(
+                canvas,
+                0,
+                &format!("{addr:03X}"),
+                if at_index { INDEX } else { TITLE },
+            )?;
+
+            let top = pen.y + pen.line_height();
+            canvas.set_draw_color(if at_index { INDEX } else { CELL });
+            canvas.fill_rect(Rect::new(x - 1, top - 1, w as u32 + 2, h as u32 + 2))?;
+            canvas.set_draw_color(CELL);
+            canvas.fill_rect(Rect::new(x, top, w as u32, h as u32))?;
+
+            let pixels = sprites::decode(&self.mem, addr, size);
+            let rects: Vec<Rect> = pixels
+                .chunks(size.width())
+                .enumerate()
+                .flat_map(|(row, line)| {
+                    line.iter()
+                        .enumerate()
+                        .filter(|(_, p)| **p == 1)
+                        .map(move |(col, _)| {
+                            Rect::new(
+                                x + col as i32 * px,
+                                top + row as i32 * px,
+                                px as u32,
+                                px as u32,
+                            )
+                        })
+                })
+                .collect();
+            canvas.set_draw_color(TEXT);
+            canvas.fill_rects(&rects)?;
+
+            x += cell_w + gap;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+//! Status text drawn over the top left of the game: frame rate, speed and
+//! pause state when shown, recording, messages and errors always.
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+
+use super::draw_text;
+use crate::font;
+use crate::frontend::{FrameState, Status};
+
+const MARGIN: i32 = 4;
+
+pub struct Hud {
+    pub visible: bool,
+    status: Status,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Hud {
+            visible: false,
+            status: Status::default(),
+        }
+    }
+
+    pub fn update(&mut self, state: &FrameState) {
+        self.status.clone_from(state.status);
+    }
+
+    fn lines(&self) -> Vec<(String, Color)> {
+        let status = &self.status;
+        let mut lines = vec![];
+
+        if self.visible {
+            lines.push((
+                format!("{} fps  {} ips", status.fps, status.ips),
+                Color::WHITE,
+            ));
+
+            let mut state = format!("speed {}x", status.speed);
+            if status.fast_forward {
+                state += "  ff";
+            }
+            if status.paused {
+                state += "  paused";
+            }
+            lines.push((state, Color::WHITE));
+        }
+
+        if status.recording {
+            lines.push(("rec".to_owned(), Color::RGB(255, 80, 80)));
+        }
+        if let Some(message) = &status.message {
+            lines.push((message.clone(), Color::RGB(255, 176, 0)));
+        }
+        if let Some(error) = &status.error {
+            lines.push((error.clone(), Color::RGB(255, 80, 80)));
+        }
+
+        lines
+    }
+
+    pub fn draw(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let lines = self.lines();
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let (win_w, win_h) = canvas.output_size()?;
+        let scale = (win_w.min(win_h * 2) / 320).max(1);
+        let line_height = (font::GLYPH_HEIGHT as u32 + 2) * scale;
+        let width = lines
+            .iter()
+            .map(|(text, _)| font::text_width(text) as u32 * scale)
+            .max()
+            .unwrap_or(0);
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        canvas.fill_rect(Rect::new(
+            MARGIN,
+            MARGIN,
+            width + MARGIN as u32 * 2,
+            line_height * lines.len() as u32 + MARGIN as u32 * 2 - 2 * scale,
+        ))?;
+        canvas.set_blend_mode(BlendMode::None);
+
+        for (i, (text, color)) in lines.iter().enumerate() {
+            let y = MARGIN * 2 + (i as u32 * line_height) as i32;
+            draw_text(canvas, text, MARGIN * 2, y, scale, *color)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! The hex keypad drawn over the corner of the game, showing which keys are
+//! held and what they're bound to. Keys can be clicked or touched.
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+
+use super::draw_text;
+use crate::font;
+use crate::frontend::FrameState;
+use crate::keymap::KEYPAD;
+
+const GAP: u32 = 4;
+
+pub struct KeypadOverlay {
+    pub visible: bool,
+    held: u16,
+    /// First host key bound to each keypad key.
+    labels: [String; 16],
+    /// Key held down by the pointer.
+    pressed: Option<usize>,
+}
+
+impl KeypadOverlay {
+    pub fn new() -> Self {
+        KeypadOverlay {
+            visible: false,
+            held: 0,
+            labels: Default::default(),
+            pressed: None,
+        }
+    }
+
+    pub fn update(&mut self, state: &FrameState) {
+        self.held = state.comp.keys();
+        for key in 0..16 {
+            let names = state.keymap.names(key);
+            // prefer keyboard keys over pad buttons for the label
+            let name = names
+                .iter()
+                .find(|n| !n.starts_with("pad_"))
+                .or(names.first())
+                .unwrap_or(&"");
+            self.labels[key] = name.chars().take(5).collect();
+        }
+    }
+
+    /// Square in the bottom right corner taking up under half the window,
+    /// or `None` if the window is too small to fit the keys.
+    fn panel(win_w: u32, win_h: u32) -> Option<Rect> {
+        let side = win_w.min(win_h) * 9 / 20;
+        if side.saturating_sub(GAP * 5) / 4 == 0 {
+            return None;
+        }
+
+        Some(Rect::new(
+            win_w.saturating_sub(side + GAP * 2) as i32,
+            win_h.saturating_sub(side + GAP * 2) as i32,
+            side,
+            side,
+        ))
+    }
+
+    /// Rect of the key at `pos`, an index into [`KEYPAD`].
+    fn cell(panel: Rect, pos: usize) -> Rect {
+        let size = panel.width().saturating_sub(GAP * 5) / 4;
+        let (col, row) = ((pos % 4) as u32, (pos / 4) as u32);
+        Rect::new(
+            panel.x() + (GAP + col * (size + GAP)) as i32,
+            panel.y() + (GAP + row * (size + GAP)) as i32,
+            size,
+            size,
+        )
+    }
+
+    fn hit(win_w: u32, win_h: u32, x: i32, y: i32) -> Option<usize> {
+        let panel = KeypadOverlay::panel(win_w, win_h)?;
+        (0..16)
+            .find(|pos| KeypadOverlay::cell(panel, *pos).contains_point((x, y)))
+            .map(|pos| KEYPAD[pos])
+    }
+
+    /// Returns the key pressed or released by a pointer event at `x`, `y`
+    /// in output pixels. The release always goes to the key that was
+    /// pressed, even if the pointer moved or the overlay was hidden since.
+    pub fn pointer(
+        &mut self,
+        win_w: u32,
+        win_h: u32,
+        x: i32,
+        y: i32,
+        down: bool,
+    ) -> Option<(usize, bool)> {
+        if down {
+            if !self.visible {
+                return None;
+            }
+            let key = KeypadOverlay::hit(win_w, win_h, x, y)?;
+            self.pressed = Some(key);
+            Some((key, true))
+        } else {
+            self.pressed.take().map(|key| (key, false))
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let (win_w, win_h) = canvas.output_size()?;
+        let Some(panel) = KeypadOverlay::panel(win_w, win_h) else {
+            return Ok(());
+        };
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        canvas.fill_rect(panel)?;
+
+        for (pos, key) in KEYPAD.iter().enumerate() {
+            let cell = KeypadOverlay::cell(panel, pos);
+            let held = self.held >> key & 1 == 1;
+            canvas.set_draw_color(if held {
+                Color::RGBA(255, 176, 0, 230)
+            } else {
+                Color::RGBA(70, 70, 70, 200)
+            });
+            canvas.fill_rect(cell)?;
+
+            let text = if held { Color::BLACK } else { Color::WHITE };
+            let scale = (cell.width() / 12).max(1);
+            let digit = format!("{key:X}");
+            let x = cell.center().x() - (font::text_width(&digit) as u32 * scale / 2) as i32;
+            draw_text(canvas, &digit, x, cell.y() + (GAP * 2) as i32, scale, text)?;
+
+            let label = &self.labels[*key];
+            let small = (scale / 2).max(1);
+            let x = cell.center().x() - (font::text_width(label) as u32 * small / 2) as i32;
+            let y = cell.bottom() - ((font::GLYPH_HEIGHT as u32 + 2) * small) as i32;
+            draw_text(canvas, label, x, y, small, text)?;
+        }
+
+        canvas.set_blend_mode(BlendMode::None);
+
+        Ok(())
+    }
+}
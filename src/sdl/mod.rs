@@ -0,0 +1,657 @@
+//! SDL2 implementations of the frontend traits.
+
+mod debugger;
+mod hud;
+mod keypad;
+
+use std::collections::{HashMap, HashSet};
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::{FullscreenType, WindowContext};
+use sdl2::{EventPump, GameControllerSubsystem, Sdl};
+
+use crate::crt::{Crt, CrtEffects};
+use crate::font;
+use crate::frontend::{AudioSink, DisplaySink, FrameState, Input, InputSource, Overlay, Scaling};
+use debugger::Debugger;
+use hud::Hud;
+use keypad::KeypadOverlay;
+
+/// Largest factor frames are scaled up by on the CPU for crt effects, the
+/// rest of the way is left to SDL.
+const MAX_CRT_SCALE: u32 = 8;
+
+/// Draws the framebuffer by uploading it into a single streaming texture
+/// sized to the chip8 screen and scaling that into the window. With crt
+/// effects the frame is scaled up on the CPU first.
+pub struct SdlDisplay<'a> {
+    canvas: WindowCanvas,
+    creator: &'a TextureCreator<WindowContext>,
+    texture: Texture<'a>,
+    pub scaling: Scaling,
+    crt: Crt,
+    pub crt_enabled: bool,
+    keypad: KeypadOverlay,
+    hud: Hud,
+    debugger: Debugger,
+}
+
+impl<'a> SdlDisplay<'a> {
+    pub fn new(
+        canvas: WindowCanvas,
+        creator: &'a TextureCreator<WindowContext>,
+    ) -> Result<Self, String> {
+        // keep pixels sharp when the texture is scaled up
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        let texture = create_screen_texture(creator, 64, 32)?;
+
+        Ok(SdlDisplay {
+            canvas,
+            creator,
+            texture,
+            scaling: Scaling::Integer,
+            crt: Crt::new(CrtEffects::default()),
+            crt_enabled: false,
+            keypad: KeypadOverlay::new(),
+            hud: Hud::new(),
+            debugger: Debugger::new(),
+        })
+    }
+
+    pub fn with_crt(mut self, effects: CrtEffects) -> Self {
+        self.crt.effects = effects;
+        self.crt_enabled = effects.any();
+        self
+    }
+
+    pub fn with_scaling(mut self, scaling: Scaling) -> Self {
+        self.scaling = scaling;
+        self
+    }
+
+    pub fn with_hud(mut self, visible: bool) -> Self {
+        self.hud.visible = visible;
+        self
+    }
+
+    pub fn with_debugger(mut self, visible: bool) -> Self {
+        self.debugger.visible = visible;
+        self
+    }
+}
+
+impl DisplaySink for SdlDisplay<'_> {
+    fn present(&mut self, frame: &[u8], width: usize, height: usize) -> Result<(), String> {
+        let (win_w, win_h) = self.canvas.output_size()?;
+        let area = if self.debugger.visible {
+            Debugger::game_rect(win_w, win_h)
+        } else {
+            Rect::new(0, 0, win_w, win_h)
+        };
+        let (x, y, w, h) =
+            self.scaling
+                .dest_rect(area.width(), area.height(), width as u32, height as u32);
+        let dest = Rect::new(area.x() + x, area.y() + y, w, h);
+
+        let (frame, width, height) = if self.crt_enabled {
+            let scale = (dest.width() / width as u32)
+                .min(dest.height() / height as u32)
+                .clamp(1, MAX_CRT_SCALE) as usize;
+            let frame = self.crt.process(frame, width, height, scale);
+            (frame, width * scale, height * scale)
+        } else {
+            (frame, width, height)
+        };
+
+        let query = self.texture.query();
+        if query.width != width as u32 || query.height != height as u32 {
+            self.texture = create_screen_texture(self.creator, width as u32, height as u32)?;
+        }
+
+        self.texture
+            .with_lock(None, |buf: &mut [u8], pitch: usize| {
+                for (y, row) in frame.chunks(width * 3).enumerate() {
+                    buf[y * pitch..y * pitch + width * 3].copy_from_slice(row);
+                }
+            })?;
+
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, Some(dest))?;
+        if self.debugger.visible {
+            self.debugger.draw(&mut self.canvas)?;
+        }
+        if self.keypad.visible {
+            self.keypad.draw(&mut self.canvas)?;
+        }
+        self.hud.draw(&mut self.canvas)?;
+        self.canvas.present();
+
+        Ok(())
+    }
+
+    fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+        let next = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(next)
+    }
+
+    fn toggle_effects(&mut self) {
+        if !self.crt.effects.any() {
+            self.crt.effects = CrtEffects::all();
+        }
+        self.crt_enabled = !self.crt_enabled;
+    }
+
+    fn update(&mut self, state: &FrameState) {
+        if self.keypad.visible {
+            self.keypad.update(state);
+        }
+        self.hud.update(state);
+        if self.debugger.visible {
+            self.debugger.update(state);
+        }
+    }
+
+    fn animating(&self) -> bool {
+        self.debugger.visible
+    }
+
+    fn toggle_overlay(&mut self, overlay: Overlay) {
+        match overlay {
+            Overlay::Keypad => self.keypad.visible = !self.keypad.visible,
+            Overlay::Hud => self.hud.visible = !self.hud.visible,
+            Overlay::Debugger => self.debugger.visible = !self.debugger.visible,
+            Overlay::Sprites => {
+                self.debugger.sprites = !self.debugger.sprites;
+                self.debugger.visible |= self.debugger.sprites;
+            }
+        }
+    }
+
+    fn pointer(&mut self, x: i32, y: i32, down: bool) -> Option<Input> {
+        // events are in window points, which are bigger than pixels on
+        // hidpi displays
+        let (out_w, out_h) = self.canvas.output_size().ok()?;
+        let (win_w, win_h) = self.canvas.window().size();
+        let x = (x as i64 * out_w as i64 / win_w.max(1) as i64) as i32;
+        let y = (y as i64 * out_h as i64 / win_h.max(1) as i64) as i32;
+        match self.keypad.pointer(out_w, out_h, x, y, down)? {
+            (key, true) => Some(Input::KeyDown(key)),
+            (key, false) => Some(Input::KeyUp(key)),
+        }
+    }
+}
+
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            *x = if self.phase <= 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+/// Square wave beeper. Falls back to silence if no audio device could be opened.
+pub struct SdlAudio {
+    device: Option<AudioDevice<SquareWave>>,
+}
+
+impl SdlAudio {
+    pub fn new(sdl_context: &Sdl) -> Self {
+        let device = sdl_context.audio().and_then(|audio| {
+            let spec = AudioSpecDesired {
+                freq: Some(44_100),
+                channels: Some(1),
+                samples: None,
+            };
+            audio.open_playback(None, &spec, |spec| SquareWave {
+                phase_inc: 440.0 / spec.freq as f32,
+                phase: 0.0,
+                volume: 0.1,
+            })
+        });
+
+        SdlAudio {
+            device: device.ok(),
+        }
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn set_tone(&mut self, on: bool) {
+        if let Some(device) = &self.device {
+            if on {
+                device.resume();
+            } else {
+                device.pause();
+            }
+        }
+    }
+}
+
+/// How far a stick has to be pushed to count as a d-pad press.
+const STICK_DEADZONE: i16 = 16_000;
+
+/// An open controller and what is held down on it.
+struct Pad {
+    /// Kept so the controller stays open while it's plugged in.
+    _controller: GameController,
+    /// Names of the buttons held.
+    buttons: HashSet<&'static str>,
+    /// Direction the left stick is pushed in on each axis, -1, 0 or 1.
+    stick: [i8; 2],
+}
+
+impl Pad {
+    /// Names of the buttons and stick directions held.
+    fn held(&self) -> impl Iterator<Item = &'static str> + '_ {
+        let stick = [["pad_left", "pad_right"], ["pad_up", "pad_down"]]
+            .into_iter()
+            .zip(self.stick)
+            .filter(|(_, dir)| *dir != 0)
+            .map(|(names, dir)| names[(dir > 0) as usize]);
+        self.buttons.iter().copied().chain(stick)
+    }
+}
+
+/// Keyboard and game controller input. Controllers are opened as they are
+/// plugged in, their buttons are reported with `pad_` names, see
+/// [`pad_button_name`]. A name stays pressed while any controller holds
+/// it, with the button or the left stick.
+pub struct SdlInput {
+    event_pump: EventPump,
+    controller_subsystem: Option<GameControllerSubsystem>,
+    /// By instance id.
+    pads: HashMap<u32, Pad>,
+}
+
+impl SdlInput {
+    pub fn new(sdl_context: &Sdl) -> Result<Self, String> {
+        Ok(SdlInput {
+            event_pump: sdl_context.event_pump()?,
+            // sdl sends a device added event for controllers that are
+            // already plugged in, so they're opened in poll like hot plugs
+            controller_subsystem: sdl_context.game_controller().ok(),
+            pads: HashMap::new(),
+        })
+    }
+
+    fn add_controller(&mut self, joystick_index: u32) {
+        let Some(subsystem) = &self.controller_subsystem else {
+            return;
+        };
+
+        if let Ok(controller) = subsystem.open(joystick_index) {
+            self.pads.insert(
+                controller.instance_id(),
+                Pad {
+                    _controller: controller,
+                    buttons: HashSet::new(),
+                    stick: [0, 0],
+                },
+            );
+        }
+    }
+
+    /// Names held on any controller.
+    fn held(&self) -> HashSet<&'static str> {
+        self.pads.values().flat_map(Pad::held).collect()
+    }
+
+    /// Handles a controller event, reporting the names it presses or
+    /// releases across all controllers.
+    fn controller_event(&mut self, event: Event, inputs: &mut Vec<Input>) {
+        let before = self.held();
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => self.add_controller(which),
+            // whatever it held is let go of
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.pads.remove(&which);
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                if let (Some(pad), Some(name)) =
+                    (self.pads.get_mut(&which), pad_button_name(button))
+                {
+                    pad.buttons.insert(name);
+                }
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                if let (Some(pad), Some(name)) =
+                    (self.pads.get_mut(&which), pad_button_name(button))
+                {
+                    pad.buttons.remove(name);
+                }
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                if let Some(pad) = self.pads.get_mut(&which) {
+                    stick_motion(pad, axis, value);
+                }
+            }
+            _ => return,
+        }
+        let after = self.held();
+
+        let mut released: Vec<&str> = before.difference(&after).copied().collect();
+        let mut pressed: Vec<&str> = after.difference(&before).copied().collect();
+        released.sort();
+        pressed.sort();
+        inputs.extend(released.into_iter().map(|n| Input::Release(n.to_owned())));
+        inputs.extend(pressed.into_iter().map(|n| Input::Press(n.to_owned())));
+    }
+}
+
+/// Turns left stick motion into d-pad directions.
+fn stick_motion(pad: &mut Pad, axis: Axis, value: i16) {
+    let idx = match axis {
+        Axis::LeftX => 0,
+        Axis::LeftY => 1,
+        _ => return,
+    };
+
+    pad.stick[idx] = if value <= -STICK_DEADZONE {
+        -1
+    } else if value >= STICK_DEADZONE {
+        1
+    } else {
+        0
+    };
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self) -> Result<Vec<Input>, String> {
+        let mut inputs = vec![];
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    inputs.push(Input::Quit);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::Fullscreen);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::CycleFilter);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::CyclePalette);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::ToggleEffects);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::BindKeys);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::ToggleOverlay(Overlay::Keypad));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::D),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    inputs.push(Input::ToggleOverlay(Overlay::Debugger));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    inputs.push(Input::ToggleOverlay(Overlay::Sprites));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    inputs.push(Input::Screenshot);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::R),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    inputs.push(Input::Record);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::ToggleOverlay(Overlay::Hud));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::Pause);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => {
+                    inputs.push(Input::AdvanceFrame);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    inputs.push(Input::StepInstruction);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::FastForward);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::Reset);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageUp),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::SpeedUp);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageDown),
+                    repeat: false,
+                    ..
+                } => {
+                    inputs.push(Input::SpeedDown);
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => {
+                    inputs.push(Input::Redraw);
+                }
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(name) = key_name(key) {
+                        inputs.push(Input::Press(name));
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => {
+                    if let Some(name) = key_name(key) {
+                        inputs.push(Input::Release(name));
+                    }
+                }
+                // sdl turns touches into mouse events too
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => inputs.push(Input::Pointer { x, y, down: true }),
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => inputs.push(Input::Pointer { x, y, down: false }),
+                Event::ControllerDeviceAdded { .. }
+                | Event::ControllerDeviceRemoved { .. }
+                | Event::ControllerButtonDown { .. }
+                | Event::ControllerButtonUp { .. }
+                | Event::ControllerAxisMotion { .. } => self.controller_event(event, &mut inputs),
+                _ => {}
+            }
+        }
+
+        Ok(inputs)
+    }
+}
+
+/// The [`KeyMap`](crate::keymap::KeyMap) name of a controller button. The
+/// left stick reports the same names as the d-pad.
+pub fn pad_button_name(button: Button) -> Option<&'static str> {
+    let name = match button {
+        Button::DPadUp => "pad_up",
+        Button::DPadDown => "pad_down",
+        Button::DPadLeft => "pad_left",
+        Button::DPadRight => "pad_right",
+        Button::A => "pad_a",
+        Button::B => "pad_b",
+        Button::X => "pad_x",
+        Button::Y => "pad_y",
+        Button::LeftShoulder => "pad_l",
+        Button::RightShoulder => "pad_r",
+        Button::Start => "pad_start",
+        Button::Back => "pad_back",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// The [`KeyMap`](crate::keymap::KeyMap) name of a key.
+fn key_name(key: Keycode) -> Option<String> {
+    let name = match key {
+        Keycode::Up => "up",
+        Keycode::Down => "down",
+        Keycode::Left => "left",
+        Keycode::Right => "right",
+        Keycode::Space => "space",
+        Keycode::Return => "enter",
+        Keycode::Tab => "tab",
+        Keycode::Backspace => "backspace",
+        Keycode::LShift => "lshift",
+        Keycode::RShift => "rshift",
+        Keycode::LCtrl => "lctrl",
+        Keycode::RCtrl => "rctrl",
+        Keycode::LAlt => "lalt",
+        Keycode::RAlt => "ralt",
+        // sdl keycodes for printable keys are their unicode values
+        _ => {
+            return char::from_u32(key.into_i32() as u32)
+                .filter(|c| !c.is_control())
+                .map(|c| c.to_lowercase().to_string())
+        }
+    };
+
+    Some(name.to_owned())
+}
+
+/// Draws `text` in the built in font with its top left corner at `x`, `y`,
+/// each font pixel `scale` pixels big.
+fn draw_text(
+    canvas: &mut WindowCanvas,
+    text: &str,
+    x: i32,
+    y: i32,
+    scale: u32,
+    color: Color,
+) -> Result<(), String> {
+    let mut rects = vec![];
+    font::for_each_pixel(text, |px, py| {
+        rects.push(Rect::new(
+            x + (px as u32 * scale) as i32,
+            y + (py as u32 * scale) as i32,
+            scale,
+            scale,
+        ));
+    });
+
+    canvas.set_draw_color(color);
+    canvas.fill_rects(&rects)
+}
+
+fn create_screen_texture(
+    creator: &TextureCreator<WindowContext>,
+    width: u32,
+    height: u32,
+) -> Result<Texture<'_>, String> {
+    creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+        .map_err(|e| e.to_string())
+}
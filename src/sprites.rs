@@ -0,0 +1,253 @@
+//! Memory decoded as sprites, to find a game's art in a ROM and check where
+//! the font is. Sprites are 8 pixels wide with a byte per row, or 16x16
+//! with two bytes per row for SUPER-CHIP's `Draw vx,vy,0`.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Sheet pixel between sprites, next to 0 for off and 1 for on.
+pub const GAP: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpriteSize {
+    /// 8 pixels wide and this many rows.
+    Rows(u8),
+    /// SUPER-CHIP's 16x16.
+    Large,
+}
+
+impl SpriteSize {
+    /// The size `Draw` with height `n` asks for.
+    pub fn from_height(n: u8) -> Self {
+        match n {
+            0 => SpriteSize::Large,
+            n => SpriteSize::Rows(n),
+        }
+    }
+
+    /// The size `Draw` with height `n` draws: 16x16 for 0 only in high
+    /// resolution, and nothing for 0 in low like the original.
+    pub fn drawn(n: u8, hires: bool) -> Self {
+        match (n, hires) {
+            (0, false) => SpriteSize::Rows(0),
+            _ => SpriteSize::from_height(n),
+        }
+    }
+
+    pub fn width(self) -> usize {
+        match self {
+            SpriteSize::Rows(_) => 8,
+            SpriteSize::Large => 16,
+        }
+    }
+
+    pub fn height(self) -> usize {
+        match self {
+            SpriteSize::Rows(n) => n as usize,
+            SpriteSize::Large => 16,
+        }
+    }
+
+    /// Bytes the sprite takes in memory.
+    pub fn bytes(self) -> usize {
+        self.width() / 8 * self.height()
+    }
+}
+
+impl FromStr for SpriteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "16x16" | "16" => Ok(SpriteSize::Large),
+            _ => match s.strip_prefix("8x").unwrap_or(s).parse() {
+                Ok(n @ 1..=15) => Ok(SpriteSize::Rows(n)),
+                _ => Err(format!(
+                    "Unknown sprite size {s}, expected 1-15 rows or 16x16"
+                )),
+            },
+        }
+    }
+}
+
+impl Display for SpriteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width(), self.height())
+    }
+}
+
+/// Pixels of the sprite at `addr`, row by row, 1 where set. Bytes past the
+/// end of `mem` read as 0.
+pub fn decode(mem: &[u8], addr: usize, size: SpriteSize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(size.width() * size.height());
+    for i in 0..size.bytes() {
+        let byte = mem.get(addr + i).copied().unwrap_or(0);
+        pixels.extend((0..8).rev().map(|bit| byte >> bit & 1));
+    }
+    pixels
+}
+
+/// Back to back sprites of one size from `start`, covering `len` bytes.
+pub fn region(start: usize, len: usize, size: SpriteSize) -> Vec<(usize, SpriteSize)> {
+    (start..start + len)
+        .step_by(size.bytes())
+        .map(|addr| (addr, size))
+        .collect()
+}
+
+/// Where a sprite went on a `Sheet`.
+#[derive(Debug, Clone, Copy)]
+pub struct Cell {
+    pub addr: usize,
+    pub size: SpriteSize,
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Sprites laid out on a grid with a `GAP` pixel around each.
+pub struct Sheet {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    pub cells: Vec<Cell>,
+    /// Size of a grid cell, without the gap.
+    pub cell: (usize, usize),
+}
+
+impl Sheet {
+    pub fn new(mem: &[u8], sprites: &[(usize, SpriteSize)], columns: usize) -> Self {
+        let columns = columns.clamp(1, sprites.len().max(1));
+        let rows = sprites.len().div_ceil(columns);
+        let cell_w = sprites.iter().map(|(_, s)| s.width()).max().unwrap_or(8);
+        let cell_h = sprites.iter().map(|(_, s)| s.height()).max().unwrap_or(1);
+
+        let width = columns * (cell_w + 1) + 1;
+        let height = rows * (cell_h + 1) + 1;
+        let mut pixels = vec![GAP; width * height];
+        let mut cells = vec![];
+
+        for (i, &(addr, size)) in sprites.iter().enumerate() {
+            let x = 1 + i % columns * (cell_w + 1);
+            let y = 1 + i / columns * (cell_h + 1);
+            // the whole cell is background so short sprites stay aligned
+            for row in 0..cell_h {
+                pixels[(y + row) * width + x..][..cell_w].fill(0);
+            }
+            for (row, line) in decode(mem, addr, size).chunks(size.width()).enumerate() {
+                pixels[(y + row) * width + x..][..line.len()].copy_from_slice(line);
+            }
+            cells.push(Cell { addr, size, x, y });
+        }
+
+        Sheet {
+            width,
+            height,
+            pixels,
+            cells,
+            cell: (cell_w, cell_h),
+        }
+    }
+
+    /// The sheet as text, `#` for set pixels, with each row of sprites
+    /// under a line of their addresses.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        let (cell_w, cell_h) = self.cell;
+
+        for y in (1..self.height).step_by(cell_h + 1) {
+            let mut labels = String::from(" ");
+            for cell in self.cells.iter().filter(|c| c.y == y) {
+                labels += &format!("{:<w$}", format!("{:03x}", cell.addr), w = cell_w + 1);
+            }
+            out += labels.trim_end();
+            out.push('\n');
+
+            for row in self.pixels[y * self.width..(y + cell_h) * self.width].chunks(self.width) {
+                let line: String = row
+                    .iter()
+                    .map(|p| match *p {
+                        0 => '.',
+                        1 => '#',
+                        _ => ' ',
+                    })
+                    .collect();
+                out += line.trim_end();
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+
+    /// The sheet as RGB, each pixel `scale` times over, with `colors` for
+    /// off, on and the gap.
+    pub fn rgb(&self, colors: [[u8; 3]; 3], scale: usize) -> Vec<u8> {
+        let scale = scale.max(1);
+        let mut out = Vec::with_capacity(self.pixels.len() * scale * scale * 3);
+        for row in self.pixels.chunks(self.width) {
+            for _ in 0..scale {
+                for p in row {
+                    for _ in 0..scale {
+                        out.extend(colors[*p as usize]);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sheet_lays_out_8_wide_sprites() {
+        let mem = [0xf0, 0x90, 0x0f, 0x09, 0xff, 0x81];
+        let sheet = Sheet::new(&mem, &region(0, 6, SpriteSize::Rows(2)), 2);
+        assert_eq!((sheet.width, sheet.height), (19, 7));
+        assert_eq!(sheet.cell, (8, 2));
+
+        let cells: Vec<_> = sheet.cells.iter().map(|c| (c.addr, c.x, c.y)).collect();
+        assert_eq!(cells, [(0, 1, 1), (2, 10, 1), (4, 1, 4)]);
+        assert_eq!(sheet.pixels[4 * 19 + 1..][..8], [1; 8]);
+        // the empty cell at the end is all gap
+        assert_eq!(sheet.pixels[4 * 19 + 10..][..8], [GAP; 8]);
+
+        let sheet = Sheet::new(&mem, &region(0, 4, SpriteSize::Rows(2)), 8);
+        assert_eq!(
+            sheet.text(),
+            " 000      002\n ####.... ....####\n #..#.... ....#..#\n"
+        );
+    }
+
+    #[test]
+    fn sheet_fits_cells_to_16x16_sprites() {
+        let mut mem = [0; 34];
+        for row in mem.chunks_mut(2) {
+            row.copy_from_slice(&[0x80, 0x01]);
+        }
+        let sprites = [(0, SpriteSize::Large), (32, SpriteSize::Rows(1))];
+        let sheet = Sheet::new(&mem, &sprites, 2);
+        assert_eq!(sheet.cell, (16, 16));
+        assert_eq!((sheet.width, sheet.height), (35, 18));
+
+        let row = &sheet.pixels[35 + 1..][..16];
+        assert_eq!((row[0], row[15]), (1, 1));
+        assert_eq!(row[1..15], [0; 14]);
+        // the short sprite's cell is background below its row
+        assert_eq!(sheet.pixels[35 + 18..][..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(sheet.pixels[2 * 35 + 18..][..16], [0; 16]);
+    }
+
+    #[test]
+    fn parses_sprite_sizes() {
+        assert_eq!("16x16".parse(), Ok(SpriteSize::Large));
+        assert_eq!("8x5".parse(), Ok(SpriteSize::Rows(5)));
+        assert_eq!("15".parse(), Ok(SpriteSize::Rows(15)));
+        assert!("8x0".parse::<SpriteSize>().is_err());
+        assert_eq!(SpriteSize::Large.bytes(), 32);
+        assert_eq!(SpriteSize::drawn(0, true), SpriteSize::Large);
+        assert_eq!(SpriteSize::drawn(0, false).bytes(), 0);
+    }
+}
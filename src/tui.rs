@@ -0,0 +1,240 @@
+//! Terminal implementations of the frontend traits. Two pixel rows are drawn
+//! per character cell with `▀`, coloring the top pixel with the foreground
+//! and the bottom one with the background, so it only needs a truecolor
+//! terminal and works fine over ssh.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, terminal};
+
+use crate::frontend::{DisplaySink, FrameState, Input, InputSource};
+
+pub struct TermDisplay {
+    out: String,
+    /// Message, error or pause state shown under the screen.
+    status: String,
+    /// The terminal reports key releases.
+    enhanced: bool,
+}
+
+impl TermDisplay {
+    /// Switches the terminal to raw mode on the alternate screen and asks
+    /// it for key release events where supported (kitty protocol). It is
+    /// all restored when the display is dropped.
+    pub fn new() -> Result<Self, String> {
+        terminal::enable_raw_mode().map_err(|e| e.to_string())?;
+        execute!(
+            io::stdout(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )
+        .map_err(|e| e.to_string())?;
+
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )
+            .map_err(|e| e.to_string())?;
+        }
+
+        Ok(TermDisplay {
+            out: String::new(),
+            status: String::new(),
+            enhanced,
+        })
+    }
+}
+
+impl Drop for TermDisplay {
+    fn drop(&mut self) {
+        // the flags belong to the alternate screen, so they go first
+        if self.enhanced {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl DisplaySink for TermDisplay {
+    fn present(&mut self, frame: &[u8], width: usize, height: usize) -> Result<(), String> {
+        use std::fmt::Write;
+
+        let pixel = |x: usize, y: usize| -> &[u8] {
+            if y < height {
+                &frame[(x + y * width) * 3..(x + y * width) * 3 + 3]
+            } else {
+                &[0, 0, 0]
+            }
+        };
+
+        let mut out = std::mem::take(&mut self.out);
+        out.clear();
+        out.push_str("\x1b[H");
+
+        for y in (0..height).step_by(2) {
+            let mut last = None;
+            for x in 0..width {
+                let (top, bottom) = (pixel(x, y), pixel(x, y + 1));
+
+                // only emit color codes when the cell differs from the last one
+                if last != Some((top, bottom)) {
+                    let _ = write!(
+                        out,
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                        top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+                    );
+                    last = Some((top, bottom));
+                }
+                out.push('▀');
+            }
+            out.push_str("\x1b[0m\r\n");
+        }
+        let _ = write!(out, "{}\x1b[K", self.status);
+
+        let mut stdout = io::stdout().lock();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|e| e.to_string())?;
+        self.out = out;
+
+        Ok(())
+    }
+
+    fn update(&mut self, state: &FrameState) {
+        let status = state.status;
+        self.status = match (&status.error, &status.message) {
+            (Some(error), _) => error.clone(),
+            (None, Some(message)) => message.clone(),
+            (None, None) if status.paused => "paused".to_owned(),
+            (None, None) if status.recording => "recording".to_owned(),
+            (None, None) => String::new(),
+        };
+    }
+}
+
+/// Frames a key stays down after its last press or repeat when the terminal
+/// can't report key releases. It has to outlast the delay before a held key
+/// starts repeating, 660ms by default on X11 and up to ~500ms elsewhere, or
+/// held keys flicker up and down. The flip side is that a quick tap holds
+/// the keypad key for the whole 2/3 of a second, which games that count
+/// frames a key is down will notice.
+const HOLD_FRAMES: u32 = 40;
+
+pub struct TermInput {
+    enhanced: bool,
+    /// Frames left until each held key is released.
+    held: HashMap<String, u32>,
+}
+
+impl TermInput {
+    /// Reads keys from the terminal `display` set up. Without key release
+    /// events, releases are faked once keys stop repeating, see
+    /// [`HOLD_FRAMES`].
+    pub fn new(display: &TermDisplay) -> Self {
+        TermInput {
+            enhanced: display.enhanced,
+            held: HashMap::new(),
+        }
+    }
+}
+
+impl InputSource for TermInput {
+    fn poll(&mut self) -> Result<Vec<Input>, String> {
+        let mut inputs = vec![];
+
+        while event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+            let Event::Key(key) = event::read().map_err(|e| e.to_string())? else {
+                continue;
+            };
+            let pressed = key.kind == KeyEventKind::Press;
+
+            let name = match key.code {
+                KeyCode::Esc => {
+                    inputs.push(Input::Quit);
+                    continue;
+                }
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    inputs.push(Input::Quit);
+                    continue;
+                }
+                KeyCode::Char(c @ ('p' | 'r')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if pressed {
+                        inputs.push(match c {
+                            'p' => Input::Screenshot,
+                            _ => Input::Record,
+                        });
+                    }
+                    continue;
+                }
+                KeyCode::F(n) => {
+                    match n {
+                        2 if pressed => inputs.push(Input::CycleFilter),
+                        3 if pressed => inputs.push(Input::CyclePalette),
+                        5 if pressed => inputs.push(Input::BindKeys),
+                        6 if pressed => inputs.push(Input::Pause),
+                        7 if key.kind != KeyEventKind::Release => inputs.push(Input::AdvanceFrame),
+                        8 if key.kind != KeyEventKind::Release => {
+                            inputs.push(Input::StepInstruction)
+                        }
+                        9 if pressed => inputs.push(Input::FastForward),
+                        10 if pressed => inputs.push(Input::Reset),
+                        _ => {}
+                    }
+                    continue;
+                }
+                KeyCode::PageUp | KeyCode::PageDown => {
+                    if pressed {
+                        inputs.push(match key.code {
+                            KeyCode::PageUp => Input::SpeedUp,
+                            _ => Input::SpeedDown,
+                        });
+                    }
+                    continue;
+                }
+                KeyCode::Char(' ') => "space".to_owned(),
+                KeyCode::Char(c) => c.to_lowercase().to_string(),
+                KeyCode::Up => "up".to_owned(),
+                KeyCode::Down => "down".to_owned(),
+                KeyCode::Left => "left".to_owned(),
+                KeyCode::Right => "right".to_owned(),
+                KeyCode::Enter => "enter".to_owned(),
+                KeyCode::Tab => "tab".to_owned(),
+                KeyCode::Backspace => "backspace".to_owned(),
+                _ => continue,
+            };
+
+            if key.kind == KeyEventKind::Release {
+                self.held.remove(&name);
+                inputs.push(Input::Release(name));
+            } else {
+                let frames = if self.enhanced { u32::MAX } else { HOLD_FRAMES };
+                if self.held.insert(name.clone(), frames).is_none() {
+                    inputs.push(Input::Press(name));
+                }
+            }
+        }
+
+        if !self.enhanced {
+            self.held.retain(|name, frames| {
+                *frames -= 1;
+                if *frames == 0 {
+                    inputs.push(Input::Release(name.clone()));
+                }
+                *frames > 0
+            });
+        }
+
+        Ok(inputs)
+    }
+}
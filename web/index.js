@@ -0,0 +1,83 @@
+import init, { Emulator, map_key } from "./pkg/chip8_web.js";
+
+const FG = [0xff, 0xff, 0xff];
+const BG = [0x00, 0x00, 0x00];
+
+await init();
+
+const canvas = document.getElementById("screen");
+const ctx = canvas.getContext("2d");
+let emu = null;
+
+function draw() {
+  const w = emu.width();
+  const h = emu.height();
+  if (canvas.width !== w || canvas.height !== h) {
+    canvas.width = w;
+    canvas.height = h;
+  }
+
+  const gfx = emu.framebuffer();
+  const img = ctx.createImageData(w, h);
+  for (let i = 0; i < gfx.length; i++) {
+    const c = gfx[i] ? FG : BG;
+    img.data.set([c[0], c[1], c[2], 0xff], i * 4);
+  }
+  ctx.putImageData(img, 0, 0);
+}
+
+const FRAME_MS = 1000 / 60;
+// frames owed are capped so a backgrounded tab doesn't catch up all at once
+const MAX_FRAMES = 4;
+let last = null;
+let owed = 0;
+
+function tick(now) {
+  requestAnimationFrame(tick);
+  if (!emu || !emu.running()) {
+    last = null;
+    return;
+  }
+
+  owed = Math.min(owed + (last === null ? FRAME_MS : now - last), MAX_FRAMES * FRAME_MS);
+  last = now;
+  try {
+    let drawn = false;
+    for (; owed >= FRAME_MS; owed -= FRAME_MS) {
+      drawn = emu.frame() || drawn;
+    }
+    if (drawn) {
+      draw();
+    }
+  } catch (e) {
+    console.error(`emu step fail: ${e} on cc ${emu.cycles()}`);
+    emu.free();
+    emu = null;
+  }
+}
+
+document.getElementById("rom").addEventListener("change", async (e) => {
+  const file = e.target.files[0];
+  if (!file) {
+    return;
+  }
+
+  if (emu) {
+    emu.free();
+  }
+  emu = new Emulator((Math.random() * 0xffffffff) >>> 0);
+  emu.load(new Uint8Array(await file.arrayBuffer()));
+  last = null;
+  owed = 0;
+});
+
+requestAnimationFrame(tick);
+
+for (const [type, fn] of [["keydown", "key_down"], ["keyup", "key_up"]]) {
+  document.addEventListener(type, (e) => {
+    const key = map_key(e.key);
+    if (emu && key !== undefined) {
+      emu[fn](key);
+    }
+  });
+}
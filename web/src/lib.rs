@@ -0,0 +1,88 @@
+//! wasm-bindgen bindings for running the core in a browser, see `index.html`.
+
+use chip8::keymap::KeyMap;
+use chip8::Chip8;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    comp: Chip8,
+    tickrate: u32,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// There is no os entropy on wasm32-unknown-unknown, so the page passes
+    /// in a seed for `Rand`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(seed: u32) -> Emulator {
+        Emulator {
+            comp: Chip8::new().with_seed(seed as u64),
+            tickrate: 20,
+        }
+    }
+
+    pub fn load(&mut self, prog: &[u8]) -> Result<(), String> {
+        self.comp.load(prog)
+    }
+
+    pub fn set_tickrate(&mut self, tickrate: u32) {
+        self.tickrate = tickrate;
+    }
+
+    pub fn step(&mut self) -> Result<(), String> {
+        self.comp.step()
+    }
+
+    /// Runs one 60hz frame, returns whether the framebuffer changed.
+    pub fn frame(&mut self) -> Result<bool, String> {
+        self.comp.frame(self.tickrate, true)?;
+        let draw = self.comp.draw;
+        self.comp.draw = false;
+        Ok(draw)
+    }
+
+    pub fn key_down(&mut self, key: usize) {
+        self.comp.key_down(key);
+    }
+
+    pub fn key_up(&mut self, key: usize) {
+        self.comp.key_up(key);
+    }
+
+    pub fn width(&self) -> usize {
+        self.comp.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.comp.height()
+    }
+
+    /// `width * height` bytes, one per pixel, 0 or 1.
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.comp.gfx.clone()
+    }
+
+    pub fn sound_on(&self) -> bool {
+        self.comp.sound_on()
+    }
+
+    pub fn running(&self) -> bool {
+        self.comp.running
+    }
+
+    pub fn cycles(&self) -> u32 {
+        self.comp.cycles
+    }
+}
+
+/// Keypad key for a `KeyboardEvent.key`, using the default qwerty layout
+/// of the native frontends.
+#[wasm_bindgen]
+pub fn map_key(key: &str) -> Option<usize> {
+    let name = match key {
+        " " => "space",
+        _ => key.strip_prefix("Arrow").unwrap_or(key),
+    };
+    KeyMap::default().get(name)
+}
@@ -0,0 +1,30 @@
+//! Run natively with `cargo test`, or in a browser through
+//! `wasm-pack test --headless --firefox`.
+
+use chip8_web::{map_key, Emulator};
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn frame_reports_draws() {
+    let mut emu = Emulator::new(1);
+    // cls, i = the font's 0, draw it at 0,0, loop
+    emu.load(&[0x00, 0xe0, 0xf0, 0x29, 0xd0, 0x05, 0x12, 0x06])
+        .unwrap();
+    // a frame stops at the first draw, the clear, then the sprite
+    assert!(emu.frame().unwrap());
+    assert!(emu.frame().unwrap());
+    assert!(emu.running());
+    assert_eq!(emu.framebuffer().len(), emu.width() * emu.height());
+    assert_eq!(emu.framebuffer()[0], 1);
+    assert!(!emu.frame().unwrap());
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn map_key_uses_the_default_layout() {
+    assert_eq!(map_key("x"), Some(0x0));
+    assert_eq!(map_key("1"), Some(0x1));
+    assert_eq!(map_key("Escape"), None);
+}